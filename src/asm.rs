@@ -0,0 +1,649 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use crate::debuginfo::{DebugInfo, MethodInfo};
+use crate::loader::{Block, IjvmFile, CONSTANT_POOL_ORIGIN, TEXT_ORIGIN};
+use crate::opcode::{Opcode, Operand};
+use crate::{Byte, Word};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub msg: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, msg: String) -> AsmError {
+        return AsmError {
+            line: self.line,
+            column: self.column,
+            msg,
+        };
+    }
+}
+
+struct Instruction<'a> {
+    op: Opcode,
+    line: usize,
+    wide: bool,
+    operands: Vec<Token<'a>>,
+}
+
+struct Method<'a> {
+    name: Token<'a>,
+    is_main: bool,
+    args: Vec<Token<'a>>,
+    vars: Vec<Token<'a>>,
+    /// Label name to the index of the instruction it precedes.
+    labels: HashMap<&'a str, usize>,
+    instructions: Vec<Instruction<'a>>,
+    end: Option<Token<'a>>,
+}
+
+impl<'a> Method<'a> {
+    /// Index of a named local: OBJREF is local 0 of a method, followed by the arguments and
+    /// the variables. The main method has no OBJREF or arguments.
+    fn local(&self, name: &str) -> Option<usize> {
+        let first_var = if self.is_main { 0 } else { self.args.len() + 1 };
+        if let Some(i) = self.args.iter().position(|a| a.text == name) {
+            return Some(i + 1);
+        }
+        return self
+            .vars
+            .iter()
+            .position(|v| v.text == name)
+            .map(|i| first_var + i);
+    }
+
+    fn declare(&self, tok: Token<'a>) -> Result<(), AsmError> {
+        if self.local(tok.text).is_some() {
+            return Err(tok.error(format!("Duplicate local variable `{}`.", tok.text)));
+        }
+        return Ok(());
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum Section {
+    Top,
+    Constant,
+    Method,
+    Var,
+}
+
+fn tokenize(line: &str, line_no: usize) -> Vec<Token<'_>> {
+    let code = match line.find("//") {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, c) in code.char_indices().chain([(code.len(), ' ')]) {
+        let sep = c.is_whitespace() || c == ',';
+        match (start, sep) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                tokens.push(Token {
+                    text: &code[s..i],
+                    line: line_no,
+                    column: s + 1,
+                });
+                start = None;
+            }
+            _ => (),
+        }
+        // A label may be directly followed by the instruction, as in `L1:BIPUSH 3`.
+        if c == ':' && tokens.is_empty() {
+            if let Some(s) = start {
+                tokens.push(Token {
+                    text: &code[s..i + 1],
+                    line: line_no,
+                    column: s + 1,
+                });
+                start = None;
+            }
+        }
+    }
+    return tokens;
+}
+
+fn parse_int(tok: &Token) -> Option<i64> {
+    let (neg, digits) = match tok.text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, tok.text),
+    };
+    let val = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    return Some(if neg { -val } else { val });
+}
+
+/// An immediate byte, which is signed (`-1`), though hex may give its unsigned bit pattern
+/// (`0xFF`).
+fn parse_byte(tok: &Token) -> Result<Byte, AsmError> {
+    let digits = tok.text.trim_start_matches('-');
+    let max = match digits.starts_with("0x") || digits.starts_with("0X") {
+        true => 255,
+        false => 127,
+    };
+    return match parse_int(tok) {
+        Some(v) if (-128..=max).contains(&v) => Ok(v as Byte),
+        Some(_) => Err(tok.error(format!(
+            "Immediate `{}` doesn't fit in a signed byte.",
+            tok.text
+        ))),
+        None => Err(tok.error(format!("Expected a number, found `{}`.", tok.text))),
+    };
+}
+
+fn parse_word(tok: &Token) -> Result<Word, AsmError> {
+    return match parse_int(tok) {
+        Some(v) if (Word::MIN as i64..=u32::MAX as i64).contains(&v) => Ok(v as Word),
+        Some(_) => Err(tok.error(format!("Constant `{}` doesn't fit in a word.", tok.text))),
+        None => Err(tok.error(format!("Expected a number, found `{}`.", tok.text))),
+    };
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    return match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+}
+
+fn identifier<'a>(tok: Token<'a>, what: &str) -> Result<Token<'a>, AsmError> {
+    if !is_identifier(tok.text) {
+        return Err(tok.error(format!("Expected {what}, found `{}`.", tok.text)));
+    }
+    return Ok(tok);
+}
+
+/// Parse the name and arguments of a `.method name(arg1, arg2)` line.
+fn method_header<'a>(
+    directive: Token<'a>,
+    line: &'a str,
+    line_no: usize,
+) -> Result<(Token<'a>, Vec<Token<'a>>), AsmError> {
+    let code = match line.find("//") {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let after = directive.column - 1 + directive.text.len();
+    let token_at = |start: usize, end: usize| {
+        let text = &code[start..end];
+        let trimmed = text.trim_start();
+        let start = start + text.len() - trimmed.len();
+        Token {
+            text: trimmed.trim_end(),
+            line: line_no,
+            column: start + 1,
+        }
+    };
+    let (open, close) = match (code.find('('), code.rfind(')')) {
+        (Some(open), Some(close)) if open > after && close > open => (open, close),
+        _ => {
+            let at = token_at(after, code.len());
+            return Err(at.error(String::from("Expected `name(args)` after `.method`.")));
+        }
+    };
+    let name = identifier(token_at(after, open), "a method name")?;
+
+    let mut args = Vec::new();
+    let mut start = open + 1;
+    for (i, c) in code[open + 1..close].char_indices() {
+        if c == ',' {
+            args.push(identifier(
+                token_at(start, open + 1 + i),
+                "an argument name",
+            )?);
+            start = open + 2 + i;
+        }
+    }
+    let last = token_at(start, close);
+    if !last.text.is_empty() || !args.is_empty() {
+        args.push(identifier(last, "an argument name")?);
+    }
+
+    let trailing = token_at(close + 1, code.len());
+    if !trailing.text.is_empty() {
+        return Err(trailing.error(format!("Unexpected `{}`.", trailing.text)));
+    }
+    return Ok((name, args));
+}
+
+struct Program<'a> {
+    constants: Vec<(Token<'a>, Word)>,
+    methods: Vec<Method<'a>>,
+}
+
+fn parse(source: &str) -> Result<Program<'_>, AsmError> {
+    let mut program = Program {
+        constants: Vec::new(),
+        methods: Vec::new(),
+    };
+    let mut section = Section::Top;
+    let mut open: Option<Token> = None;
+    let mut wide: Option<Token> = None;
+    let mut last_line = 0;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        last_line = line_no;
+        let mut tokens = tokenize(line, line_no);
+        if tokens.is_empty() {
+            continue;
+        }
+        let first = tokens[0];
+
+        if first.text.starts_with('.') {
+            let expect_args = |n: usize| {
+                if tokens.len() > n {
+                    let t = tokens[n];
+                    return Err(t.error(format!("Unexpected `{}`.", t.text)));
+                }
+                return Ok(());
+            };
+            let method = program.methods.last_mut();
+            match (first.text, &section) {
+                (".constant", Section::Top) => {
+                    expect_args(1)?;
+                    section = Section::Constant;
+                    open = Some(first);
+                }
+                (".end-constant", Section::Constant) => {
+                    expect_args(1)?;
+                    section = Section::Top;
+                }
+                (".main", Section::Top) | (".method", Section::Top) => {
+                    let is_main = first.text == ".main";
+                    let (name, args) = if is_main {
+                        expect_args(1)?;
+                        if program.methods.iter().any(|m| m.is_main) {
+                            return Err(first.error(String::from("Duplicate `.main`.")));
+                        }
+                        (first, Vec::new())
+                    } else {
+                        method_header(first, line, line_no)?
+                    };
+                    if !is_main {
+                        if program
+                            .methods
+                            .iter()
+                            .any(|m| !m.is_main && m.name.text == name.text)
+                        {
+                            return Err(name.error(format!("Duplicate method `{}`.", name.text)));
+                        }
+                        for (j, arg) in args.iter().enumerate() {
+                            if args[..j].iter().any(|a| a.text == arg.text) {
+                                return Err(
+                                    arg.error(format!("Duplicate argument `{}`.", arg.text))
+                                );
+                            }
+                        }
+                    }
+                    program.methods.push(Method {
+                        name,
+                        is_main,
+                        args,
+                        vars: Vec::new(),
+                        labels: HashMap::new(),
+                        instructions: Vec::new(),
+                        end: None,
+                    });
+                    section = Section::Method;
+                    open = Some(first);
+                }
+                (".end-main", Section::Method) | (".end-method", Section::Method) => {
+                    expect_args(1)?;
+                    let method = method.expect("in a method section");
+                    if method.is_main != (first.text == ".end-main") {
+                        return Err(first.error(format!(
+                            "Expected `{}`, found `{}`.",
+                            if method.is_main {
+                                ".end-main"
+                            } else {
+                                ".end-method"
+                            },
+                            first.text
+                        )));
+                    }
+                    if let Some(w) = wide {
+                        return Err(w.error(String::from("Expected an instruction after `WIDE`.")));
+                    }
+                    method.end = Some(first);
+                    section = Section::Top;
+                }
+                (".var", Section::Method) => {
+                    expect_args(1)?;
+                    let method = method.expect("in a method section");
+                    if !method.instructions.is_empty() || !method.labels.is_empty() {
+                        return Err(first.error(String::from(
+                            "Expected `.var` before the first instruction.",
+                        )));
+                    }
+                    section = Section::Var;
+                    open = Some(first);
+                }
+                (".end-var", Section::Var) => {
+                    expect_args(1)?;
+                    section = Section::Method;
+                }
+                _ => {
+                    return Err(first.error(format!("Unexpected directive `{}`.", first.text)));
+                }
+            }
+            continue;
+        }
+
+        match section {
+            Section::Top => {
+                return Err(first.error(format!(
+                    "Expected `.constant`, `.main` or `.method`, found `{}`.",
+                    first.text
+                )));
+            }
+            Section::Constant => {
+                let name = identifier(first, "a constant name")?;
+                if tokens.len() != 2 {
+                    let at = tokens.get(2).unwrap_or(&first);
+                    return Err(at.error(String::from("Expected `name value`.")));
+                }
+                if program.constants.iter().any(|(c, _)| c.text == name.text) {
+                    return Err(name.error(format!("Duplicate constant `{}`.", name.text)));
+                }
+                program.constants.push((name, parse_word(&tokens[1])?));
+            }
+            Section::Var => {
+                let method = program.methods.last_mut().expect("in a var section");
+                for tok in tokens {
+                    let var = identifier(tok, "a variable name")?;
+                    method.declare(var)?;
+                    method.vars.push(var);
+                }
+            }
+            Section::Method => {
+                let method = program.methods.last_mut().expect("in a method section");
+                if let Some(label) = first.text.strip_suffix(':') {
+                    let tok = identifier(
+                        Token {
+                            text: label,
+                            ..first
+                        },
+                        "a label",
+                    )?;
+                    if method.labels.contains_key(label) {
+                        return Err(tok.error(format!("Duplicate label `{label}`.")));
+                    }
+                    method.labels.insert(label, method.instructions.len());
+                    tokens.remove(0);
+                }
+                let Some(mnemonic) = tokens.first().copied() else {
+                    continue;
+                };
+                let Some(op) = Opcode::from_mnemonic(mnemonic.text) else {
+                    return Err(mnemonic.error(format!("Unknown instruction `{}`.", mnemonic.text)));
+                };
+                if op == Opcode::Wide {
+                    if tokens.len() > 1 {
+                        let t = tokens[1];
+                        return Err(t.error(format!("Unexpected `{}`.", t.text)));
+                    }
+                    wide = Some(mnemonic);
+                    continue;
+                }
+                if let Some(w) = wide {
+                    if !op.can_be_wide() {
+                        return Err(w.error(format!("`WIDE` can't prefix `{op}`.")));
+                    }
+                }
+                let operands = tokens[1..].to_vec();
+                if operands.len() != op.operands().len() {
+                    let at = operands.get(op.operands().len()).unwrap_or(&mnemonic);
+                    return Err(at.error(format!(
+                        "`{op}` takes {} operand(s), found {}.",
+                        op.operands().len(),
+                        operands.len()
+                    )));
+                }
+                method.instructions.push(Instruction {
+                    op,
+                    line: mnemonic.line,
+                    wide: wide.take().is_some(),
+                    operands,
+                });
+            }
+        }
+    }
+
+    if section != Section::Top {
+        let open = open.expect("a section was opened");
+        return Err(AsmError {
+            line: last_line,
+            column: 1,
+            msg: format!(
+                "Expected the end of `{}` from line {}, found the end of the file.",
+                open.text, open.line
+            ),
+        });
+    }
+    if !program.methods.iter().any(|m| m.is_main) {
+        return Err(AsmError {
+            line: last_line,
+            column: 1,
+            msg: String::from("Expected a `.main` method."),
+        });
+    }
+    return Ok(program);
+}
+
+/// The local index an ILOAD, ISTORE or IINC refers to.
+fn local_index(method: &Method, tok: &Token) -> Result<usize, AsmError> {
+    if let Some(i) = method.local(tok.text) {
+        return Ok(i);
+    }
+    return match parse_int(tok) {
+        Some(i) if (0..=u16::MAX as i64).contains(&i) => Ok(i as usize),
+        _ => Err(tok.error(format!("Unknown local variable `{}`.", tok.text))),
+    };
+}
+
+/// Whether the instruction needs a WIDE prefix. Local indices above 255 get one whether
+/// or not the source has it.
+fn is_wide(method: &Method, instr: &Instruction) -> Result<bool, AsmError> {
+    if instr.wide {
+        return Ok(true);
+    }
+    for (kind, tok) in instr.op.operands().iter().zip(&instr.operands) {
+        if *kind == Operand::Var && local_index(method, tok)? > Byte::MAX as usize {
+            return Ok(true);
+        }
+    }
+    return Ok(false);
+}
+
+fn instruction_size(method: &Method, instr: &Instruction) -> Result<usize, AsmError> {
+    let wide = is_wide(method, instr)?;
+    return Ok(instr.op.encoded_len(wide) + if wide { 1 } else { 0 });
+}
+
+/// Assemble JAS source into a binary with a constant pool and a text block.
+pub fn assemble(source: &str) -> Result<IjvmFile, AsmError> {
+    return Ok(assemble_with_debug_info(source)?.0);
+}
+
+/// Like [`assemble`], but also map the binary back to the source. The caller fills in
+/// [`DebugInfo::file`].
+pub fn assemble_with_debug_info(source: &str) -> Result<(IjvmFile, DebugInfo), AsmError> {
+    let mut program = parse(source)?;
+    // Main always comes first so that execution starts there.
+    program.methods.sort_by_key(|m| !m.is_main);
+
+    // Method addresses are appended to the constant pool, after the named constants.
+    let mut method_indices: HashMap<&str, usize> = HashMap::new();
+    for m in program.methods.iter().filter(|m| !m.is_main) {
+        method_indices.insert(m.name.text, program.constants.len() + method_indices.len());
+    }
+
+    let mut method_addrs = Vec::new();
+    let mut instr_addrs: Vec<Vec<usize>> = Vec::new();
+    let mut addr = 0;
+    for method in &program.methods {
+        method_addrs.push(addr);
+        if !method.is_main {
+            addr += 4;
+        }
+        let mut addrs = Vec::new();
+        for instr in &method.instructions {
+            addrs.push(addr);
+            addr += instruction_size(method, instr)?;
+        }
+        addrs.push(addr);
+        instr_addrs.push(addrs);
+    }
+    if addr > (CONSTANT_POOL_ORIGIN - TEXT_ORIGIN) as usize {
+        let end = program
+            .methods
+            .last()
+            .and_then(|m| m.end)
+            .expect("methods are closed");
+        return Err(end.error(format!("Program text is too large ({addr} bytes).")));
+    }
+
+    let mut cp: Vec<Byte> = Vec::new();
+    for (_, val) in &program.constants {
+        cp.extend(val.to_be_bytes());
+    }
+    for (method, addr) in program.methods.iter().zip(&method_addrs) {
+        if !method.is_main {
+            cp.extend((*addr as Word + TEXT_ORIGIN).to_be_bytes());
+        }
+    }
+
+    let mut info = DebugInfo::default();
+    for ((method, addrs), start) in program.methods.iter().zip(&instr_addrs).zip(&method_addrs) {
+        let origin = TEXT_ORIGIN;
+        let mut locals = BTreeMap::new();
+        for name in method.args.iter().chain(&method.vars) {
+            let index = method.local(name.text).expect("declared");
+            locals.insert(index as u16, name.text.to_string());
+        }
+        info.methods.push(MethodInfo {
+            name: String::from(if method.is_main {
+                "main"
+            } else {
+                method.name.text
+            }),
+            start: origin + *start as Word,
+            end: origin + *addrs.last().expect("ends with the method's end") as Word,
+            locals,
+        });
+        for (label, &i) in &method.labels {
+            info.labels
+                .push((origin + addrs[i] as Word, label.to_string()));
+        }
+        for (instr, addr) in method.instructions.iter().zip(addrs) {
+            info.lines.insert(origin + *addr as Word, instr.line);
+        }
+    }
+    info.labels.sort();
+
+    let mut text: Vec<Byte> = Vec::new();
+    for (method, addrs) in program.methods.iter().zip(&instr_addrs) {
+        if !method.is_main {
+            let num_args = method.args.len() + 1; // OBJREF counts as an argument.
+            let num_vars = method.vars.len();
+            if num_args > u16::MAX as usize || num_vars > u16::MAX as usize {
+                return Err(method
+                    .name
+                    .error(format!("Too many locals in method `{}`.", method.name.text)));
+            }
+            text.extend((num_args as u16).to_be_bytes());
+            text.extend((num_vars as u16).to_be_bytes());
+        }
+        for (i, instr) in method.instructions.iter().enumerate() {
+            let wide = is_wide(method, instr)?;
+            if wide {
+                text.push(Opcode::Wide.byte());
+            }
+            text.push(instr.op.byte());
+            for (kind, tok) in instr.op.operands().iter().zip(&instr.operands) {
+                match kind {
+                    Operand::Byte => text.push(parse_byte(tok)?),
+                    Operand::Var => {
+                        let index = local_index(method, tok)?;
+                        if wide {
+                            text.extend((index as u16).to_be_bytes());
+                        } else {
+                            text.push(index as Byte);
+                        }
+                    }
+                    Operand::Offset => {
+                        let Some(&target) = method.labels.get(tok.text) else {
+                            return Err(tok.error(format!("Unknown label `{}`.", tok.text)));
+                        };
+                        let offset = addrs[target] as i64 - addrs[i] as i64;
+                        if offset < i16::MIN as i64 || offset > i16::MAX as i64 {
+                            return Err(tok.error(format!(
+                                "Label `{}` is too far away ({offset} bytes).",
+                                tok.text
+                            )));
+                        }
+                        text.extend((offset as i16).to_be_bytes());
+                    }
+                    Operand::Constant => {
+                        let Some(index) = program
+                            .constants
+                            .iter()
+                            .position(|(c, _)| c.text == tok.text)
+                        else {
+                            return Err(tok.error(format!("Unknown constant `{}`.", tok.text)));
+                        };
+                        text.extend((index as u16).to_be_bytes());
+                    }
+                    Operand::Method => {
+                        let Some(&index) = method_indices.get(tok.text) else {
+                            return Err(tok.error(format!("Unknown method `{}`.", tok.text)));
+                        };
+                        text.extend((index as u16).to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    let blocks = vec![
+        Block {
+            origin: CONSTANT_POOL_ORIGIN,
+            data: cp,
+        },
+        Block {
+            origin: TEXT_ORIGIN,
+            data: text,
+        },
+    ];
+    let program = IjvmFile::new(blocks).expect("text fits below the constant pool");
+    return Ok((program, info));
+}
@@ -0,0 +1,590 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use crate::debuginfo::DebugInfo;
+use crate::disasm::{decode, decode_program, method_names, Item};
+use crate::loader::IjvmFile;
+use crate::machine::Machine;
+use crate::opcode::{Opcode, Operand};
+use crate::Word;
+
+const HELP: &str = "\
+break <pc|label>     Stop before the instruction at <pc>, e.g. 0x1a, L26, method1, or with
+                     debug info a method, a source line such as foo.jas:12 or :12, or a
+                     label, which is looked up in the current method first and can be
+                     given as method:label
+delete <pc|label>    Remove a breakpoint
+watch <local>        Stop when local variable <local> (an index or, with debug info, a name)
+                     of the current frame changes
+info                 List breakpoints and watches
+step [n]             Execute <n> instructions, 1 by default
+next                 Like step, but run a method called by INVOKEVIRTUAL, or by TAILCALL
+                     in main, until it returns
+finish               Run until the current method returns
+continue             Run until a breakpoint, a watch or the end of the program
+print stack          The current frame's operand stack
+print locals         The current frame's local variables
+print frame          Where the current frame is and what it belongs to
+backtrace            The methods that are being executed and their arguments, innermost first
+print constant <i>   Constant pool entry <i>
+print heap           The arrays created so far
+print array <ref>    The array that reference <ref>, e.g. 0xa000001, refers to
+quit                 Stop debugging
+An empty line repeats the last command.";
+
+struct Watch {
+    /// The local's name if known, or else its index.
+    name: String,
+    /// Call depth of the frame the local belongs to.
+    depth: usize,
+    /// Stack index of the local.
+    slot: Word,
+    value: Word,
+}
+
+/// A gdb-style debugger driving a [`Machine`] one `step` at a time.
+pub struct Debugger {
+    machine: Machine,
+    /// Method names and labels, by address including the text block's origin. A method's
+    /// name comes before any labels at its first instruction.
+    symbols: BTreeMap<Word, Vec<String>>,
+    breakpoints: BTreeSet<Word>,
+    watches: Vec<Watch>,
+    max_steps: Option<u64>,
+    last_command: String,
+    debug_info: Option<DebugInfo>,
+}
+
+impl Debugger {
+    /// Debug `machine`, which has to be running `program`.
+    pub fn new(machine: Machine, program: &IjvmFile) -> Debugger {
+        let origin = program.text().origin;
+        let mut symbols = BTreeMap::new();
+        symbols.insert(origin, vec![String::from("main")]);
+        // Break at a method's first instruction rather than its header.
+        for (start, name) in method_names(&decode_program(program).regions) {
+            symbols.insert(origin.wrapping_add(start as Word + 4), vec![name]);
+        }
+        return Debugger {
+            machine,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            max_steps: None,
+            last_command: String::from("step"),
+            debug_info: None,
+        };
+    }
+
+    /// Use the source's names and lines, which replace the disassembler's `L26` and `method1`.
+    pub fn debug_info(mut self, info: DebugInfo) -> Debugger {
+        self.symbols.clear();
+        for method in &info.methods {
+            let first = if method.name == "main" {
+                method.start
+            } else {
+                method.start.wrapping_add(4)
+            };
+            self.symbols
+                .entry(first)
+                .or_default()
+                .push(method.name.clone());
+        }
+        for (addr, label) in &info.labels {
+            self.symbols.entry(*addr).or_default().push(label.clone());
+        }
+        self.debug_info = Some(info);
+        return self;
+    }
+
+    /// Stop running once the machine has executed `max_steps` instructions in total.
+    pub fn max_steps(mut self, max_steps: Option<u64>) -> Debugger {
+        self.max_steps = max_steps;
+        return self;
+    }
+
+    pub fn machine(&self) -> &Machine {
+        return &self.machine;
+    }
+
+    pub fn into_machine(self) -> Machine {
+        return self.machine;
+    }
+
+    /// Read commands from `input` until it ends, the program halts or `quit`.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        writeln!(out, "Type help for a list of commands.")?;
+        let mut lines = input.lines();
+        while !self.machine.halted() {
+            self.machine.flush_tracer();
+            write!(out, "{}\n(ijvrust) ", self.location())?;
+            out.flush()?;
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let line = line?;
+            if matches!(line.trim(), "q" | "quit") {
+                break;
+            }
+            let reply = self.execute(&line);
+            if !reply.is_empty() {
+                writeln!(out, "{reply}")?;
+            }
+        }
+        self.machine.flush_tracer();
+        match self.machine.halt_reason() {
+            Some(reason) if reason.is_success() => writeln!(out, "{reason}")?,
+            Some(_) => {
+                let report = self.machine.report();
+                match &self.debug_info {
+                    Some(info) => writeln!(out, "{}", report.debug_info(info))?,
+                    None => writeln!(out, "{report}")?,
+                }
+            }
+            None => (),
+        }
+        return Ok(());
+    }
+
+    /// Run a single command and return what it has to say.
+    pub fn execute(&mut self, line: &str) -> String {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            ["h" | "help"] => Ok(String::from(HELP)),
+            ["b" | "break", at] => self.resolve(at).map(|pc| {
+                self.breakpoints.insert(pc);
+                return format!("Breakpoint at {}", self.name(pc));
+            }),
+            ["d" | "delete", at] => {
+                self.resolve(at)
+                    .and_then(|pc| match self.breakpoints.remove(&pc) {
+                        true => Ok(format!("Deleted breakpoint at {}", self.name(pc))),
+                        false => Err(format!("No breakpoint at {}", self.name(pc))),
+                    })
+            }
+            ["w" | "watch", index] => self.watch(index),
+            ["i" | "info"] => Ok(self.info()),
+            ["s" | "step"] => Ok(self.step(1)),
+            ["s" | "step", n] => match n.parse() {
+                Ok(n) => Ok(self.step(n)),
+                Err(e) => Err(format!("Invalid step count {n}: {e}")),
+            },
+            ["n" | "next"] => Ok(self.next()),
+            ["f" | "finish"] => self.finish(),
+            ["c" | "continue"] => Ok(self.resume(|_| false)),
+            ["p" | "print"] | ["p" | "print", "stack"] => {
+                Ok(format!("{:?}", self.machine.stack().operands()))
+            }
+            ["p" | "print", "locals"] => Ok(self.locals()),
+            ["p" | "print", "frame"] => self.frame(),
+            ["bt" | "backtrace"] => Ok(self.backtrace()),
+            ["p" | "print", "constant", i] => match i.parse() {
+                Ok(i) => match self.machine.constant(i) {
+                    Some(c) => Ok(format!("CONST{i} = {c} ({c:#x})")),
+                    None => Err(format!("There's no constant {i}")),
+                },
+                Err(e) => Err(format!("Invalid constant index {i}: {e}")),
+            },
+            ["p" | "print", "heap"] => Ok(self.heap()),
+            ["p" | "print", "array", reference] => self.array(reference),
+            _ => Err(format!("Unknown command {line}, try help")),
+        };
+        return match result {
+            Ok(reply) => reply,
+            Err(e) => e,
+        };
+    }
+
+    /// Turn `0x1a`, `26`, `L26`, `main` or `method1` into an address, or with debug info also
+    /// `foo.jas:12`, `:12`, `loop` or `main:loop`.
+    fn resolve(&self, at: &str) -> Result<Word, String> {
+        let named: Vec<Word> = self
+            .symbols
+            .iter()
+            .filter(|(_, names)| names.iter().any(|name| name == at))
+            .map(|(&pc, _)| pc)
+            .collect();
+        match named.as_slice() {
+            [] => (),
+            [pc] => return Ok(*pc),
+            _ => return self.resolve_ambiguous(at, &named),
+        }
+        if let (Some(info), Some((prefix, suffix))) = (&self.debug_info, at.rsplit_once(':')) {
+            let Ok(line) = suffix.parse::<usize>() else {
+                return resolve_scoped_label(info, prefix, suffix);
+            };
+            return info
+                .addr_of_line(line)
+                .ok_or_else(|| format!("There's no code on or after line {line}"));
+        }
+        let number = match (at.strip_prefix("0x"), at.strip_prefix('L')) {
+            (Some(hex), _) => Word::from_str_radix(hex, 16),
+            // The disassembler's labels are relative to the text block.
+            (_, Some(label)) => label
+                .parse()
+                .map(|l: Word| l.wrapping_add(self.machine.text_origin)),
+            _ => at.parse(),
+        };
+        return number.map_err(|_| format!("{at} is neither an address nor a known label"));
+    }
+
+    /// Labels are scoped to their method, so several methods may have one called `at`. Takes
+    /// the current method's, if it has one.
+    fn resolve_ambiguous(&self, at: &str, candidates: &[Word]) -> Result<Word, String> {
+        let Some(info) = &self.debug_info else {
+            return Ok(candidates[0]);
+        };
+        if let Some(current) = info.method_at(self.machine.pc()) {
+            let in_current =
+                |pc: Word| info.method_at(pc).is_some_and(|m| m.start == current.start);
+            if let Some(&pc) = candidates.iter().find(|&&pc| in_current(pc)) {
+                return Ok(pc);
+            }
+        }
+        let qualified: Vec<String> = candidates
+            .iter()
+            .map(|&pc| match info.method_at(pc) {
+                Some(method) => format!("{}:{at} at {pc:#06x}", method.name),
+                None => format!("{pc:#06x}"),
+            })
+            .collect();
+        return Err(format!(
+            "{at} is in several methods, try one of {}",
+            qualified.join(", ")
+        ));
+    }
+
+    fn name(&self, pc: Word) -> String {
+        return match self.symbols.get(&pc) {
+            Some(names) => format!("{pc:#06x} ({})", names.join(", ")),
+            None => format!("{pc:#06x}"),
+        };
+    }
+
+    fn method_name(&self) -> String {
+        return self.method_name_at(self.machine.method_addr(), self.machine.call_depth() == 1);
+    }
+
+    /// The name of the method starting at `start`, which is main's if `is_main`.
+    fn method_name_at(&self, start: Word, is_main: bool) -> String {
+        let first = if is_main {
+            start
+        } else {
+            start.wrapping_add(4)
+        };
+        return match self.symbols.get(&first) {
+            Some(names) => names[0].clone(),
+            None => format!("method at {start:#06x}"),
+        };
+    }
+
+    /// The next instruction and where it is.
+    fn location(&self) -> String {
+        let pc = self.machine.pc();
+        let relative = pc.wrapping_sub(self.machine.text_origin) as usize;
+        let text = &self.machine.text;
+        let instruction = match relative {
+            _ if relative == text.len() => String::from("end of text"),
+            _ if relative > text.len() => String::from("outside of the text"),
+            _ => match decode(text, relative, text.len()) {
+                Item::Instruction {
+                    wide, op, operands, ..
+                } => {
+                    let mut line = String::from(if wide { "WIDE " } else { "" });
+                    line += op.mnemonic();
+                    for (kind, val) in operands {
+                        match kind {
+                            Operand::Offset => {
+                                write!(
+                                    line,
+                                    " {}",
+                                    self.name(self.machine.text_origin.wrapping_add(val))
+                                )
+                            }
+                            Operand::Var => write!(line, " {}", self.local_name(val as u16)),
+                            _ => write!(line, " {val}"),
+                        }
+                        .unwrap();
+                    }
+                    line
+                }
+                Item::Unknown { byte, .. } => format!(".byte {byte:#04x}"),
+            },
+        };
+        let source = match self.debug_info.as_ref().and_then(|i| i.line_at(pc)) {
+            Some(line) => match self.debug_info.as_ref().and_then(|i| i.file.as_ref()) {
+                Some(file) => format!(" at {file}:{line}"),
+                None => format!(" at line {line}"),
+            },
+            None => String::new(),
+        };
+        return format!(
+            "{}{source} in {}: {instruction}",
+            self.name(pc),
+            self.method_name()
+        );
+    }
+
+    /// The name of local `index` of the current method, or just the index.
+    fn local_name(&self, index: u16) -> String {
+        let pc = self.machine.pc();
+        return match self
+            .debug_info
+            .as_ref()
+            .and_then(|i| i.local_name(pc, index))
+        {
+            Some(name) => name.to_string(),
+            None => index.to_string(),
+        };
+    }
+
+    fn watch(&mut self, index: &str) -> Result<String, String> {
+        let pc = self.machine.pc();
+        let named = self
+            .debug_info
+            .as_ref()
+            .and_then(|i| i.method_at(pc))
+            .and_then(|m| m.locals.iter().find(|(_, name)| *name == index));
+        let index: u16 = match named {
+            Some((&i, _)) => i,
+            None => index
+                .parse()
+                .map_err(|_| format!("{index} is neither a local's index nor its name"))?,
+        };
+        let stack = self.machine.stack();
+        if index as usize >= stack.locals().len() {
+            return Err(format!(
+                "The current frame only has {} locals",
+                stack.locals().len()
+            ));
+        }
+        let slot = stack.local_slot(index) as Word;
+        let value = stack[slot];
+        let name = self.local_name(index);
+        self.watches.push(Watch {
+            name: name.clone(),
+            depth: self.machine.call_depth(),
+            slot,
+            value,
+        });
+        return Ok(format!(
+            "Watching local {name} of {}, now {value}",
+            self.method_name()
+        ));
+    }
+
+    fn info(&self) -> String {
+        let mut out = String::new();
+        if self.breakpoints.is_empty() && self.watches.is_empty() {
+            return String::from("No breakpoints or watches");
+        }
+        for &pc in &self.breakpoints {
+            writeln!(out, "Breakpoint at {}", self.name(pc)).unwrap();
+        }
+        for w in &self.watches {
+            writeln!(
+                out,
+                "Watching local {} at call depth {}, now {}",
+                w.name, w.depth, w.value
+            )
+            .unwrap();
+        }
+        return out.trim_end().to_string();
+    }
+
+    fn locals(&self) -> String {
+        let locals = self.machine.stack().locals();
+        let mut out = String::new();
+        for (i, val) in locals.iter().enumerate() {
+            let note = if i == 0 && self.machine.call_depth() > 1 {
+                String::from(" (link pointer)")
+            } else {
+                match self.local_name(i as u16) {
+                    name if name != i.to_string() => format!(" ({name})"),
+                    _ => String::new(),
+                }
+            };
+            writeln!(out, "{i:>5}: {val}{note}").unwrap();
+        }
+        if locals.is_empty() {
+            out += "No locals";
+        }
+        return out.trim_end().to_string();
+    }
+
+    fn frame(&self) -> Result<String, String> {
+        let Some(frame) = self.machine.frames().next() else {
+            return Err(String::from(
+                "The current frame's link pointer is corrupted",
+            ));
+        };
+        let returns = match frame.return_pc {
+            Some(pc) => format!(", returns to {}", self.name(pc)),
+            None => String::new(),
+        };
+        return Ok(format!(
+            "{} at depth {}, started at {:#06x}{returns}\nPC {:#06x}, LV {}, SP {}, {} locals, {} arguments {:?}, {} operands",
+            self.method_name_at(frame.method, frame.is_main()),
+            self.machine.call_depth(),
+            frame.method,
+            frame.pc,
+            frame.lv,
+            self.machine.stack().sp(),
+            frame.locals.len(),
+            frame.args.len(),
+            frame.args,
+            frame.operands.len()
+        ));
+    }
+
+    /// One line per frame, innermost first.
+    fn backtrace(&self) -> String {
+        let mut out = String::new();
+        let mut shown = 0;
+        for (i, frame) in self.machine.frames().enumerate() {
+            let source = match self.debug_info.as_ref().and_then(|i| i.location(frame.pc)) {
+                Some(location) => format!(" at {location}"),
+                None => String::new(),
+            };
+            writeln!(
+                out,
+                "#{i} {} in {} {:?}{source}",
+                self.name(frame.pc),
+                self.method_name_at(frame.method, frame.is_main()),
+                frame.args
+            )
+            .unwrap();
+            shown += 1;
+        }
+        if shown < self.machine.call_depth() {
+            out += "The rest of the stack is corrupted.";
+        }
+        return out.trim_end().to_string();
+    }
+
+    fn heap(&self) -> String {
+        let heap = self.machine.heap();
+        let stats = heap.stats();
+        let mut out = format!(
+            "{} arrays, {} words; {} collections freed {} arrays, {} bytes",
+            heap.len(),
+            heap.words(),
+            stats.collections,
+            stats.arrays_freed,
+            stats.bytes_freed()
+        );
+        for (reference, array) in heap.arrays() {
+            write!(out, "\n{reference:#010x}: {} words", array.len()).unwrap();
+        }
+        return out;
+    }
+
+    fn array(&self, reference: &str) -> Result<String, String> {
+        let value = match reference.strip_prefix("0x") {
+            Some(hex) => Word::from_str_radix(hex, 16),
+            None => reference.parse(),
+        }
+        .map_err(|_| format!("Invalid reference {reference}"))?;
+        return match self.machine.heap().get(value) {
+            Some(array) => Ok(format!("{array:?}")),
+            None => Err(format!("{value:#x} doesn't refer to an array")),
+        };
+    }
+
+    fn step(&mut self, n: u64) -> String {
+        let target = self.machine.steps() + n;
+        return self.resume(|m| m.steps() >= target);
+    }
+
+    fn next(&mut self) -> String {
+        // TAILCALL only pushes a frame in main, elsewhere it replaces the current one.
+        let calls = match self.machine.next_opcode() {
+            Some(Opcode::Invokevirtual) => true,
+            Some(Opcode::Tailcall) => self.machine.call_depth() == 1,
+            _ => false,
+        };
+        if !calls {
+            return self.step(1);
+        }
+        let depth = self.machine.call_depth();
+        return self.resume(|m| m.call_depth() <= depth);
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let depth = self.machine.call_depth();
+        if depth == 1 {
+            return Err(String::from("main doesn't return, try continue"));
+        }
+        return Ok(self.resume(|m| m.call_depth() < depth));
+    }
+
+    /// Step until `done`, a breakpoint or a watch says to stop, or the machine halts.
+    fn resume(&mut self, done: impl Fn(&Machine) -> bool) -> String {
+        loop {
+            if self.machine.halted() {
+                return String::new();
+            }
+            if self
+                .max_steps
+                .is_some_and(|max| self.machine.steps() >= max)
+            {
+                return format!("Stopped after {} steps", self.machine.steps());
+            }
+            self.machine.step();
+            if let Some(msg) = self.check_watches() {
+                return msg;
+            }
+            if self.machine.halted() || done(&self.machine) {
+                return String::new();
+            }
+            let pc = self.machine.pc();
+            if self.breakpoints.contains(&pc) {
+                return format!("Breakpoint at {}", self.name(pc));
+            }
+        }
+    }
+
+    fn check_watches(&mut self) -> Option<String> {
+        let depth = self.machine.call_depth();
+        let stack = self.machine.stack();
+        let mut msg = None;
+        self.watches.retain_mut(|w| {
+            if depth < w.depth {
+                msg = Some(format!(
+                    "Local {} went out of scope, no longer watching it",
+                    w.name
+                ));
+                return false;
+            }
+            let value = stack[w.slot];
+            if value != w.value {
+                msg = Some(format!(
+                    "Local {} changed from {} to {value}",
+                    w.name, w.value
+                ));
+                w.value = value;
+            }
+            return true;
+        });
+        return msg;
+    }
+}
+
+/// The address of `label` in `method`.
+fn resolve_scoped_label(info: &DebugInfo, method: &str, label: &str) -> Result<Word, String> {
+    let Some(method) = info.methods.iter().find(|m| m.name == method) else {
+        return Err(format!("There's no method {method}"));
+    };
+    return info
+        .labels
+        .iter()
+        .find(|(pc, name)| method.start <= *pc && *pc < method.end && name == label)
+        .map(|(pc, _)| *pc)
+        .ok_or_else(|| format!("Method {} has no label {label}", method.name));
+}
@@ -0,0 +1,214 @@
+//! A side table relating a binary back to its source, written next to it as `foo.dbg`.
+//!
+//! The format is plain text, one entry per line, so that other assemblers can write it too:
+//!
+//! ```text
+//! # Comments and empty lines are ignored.
+//! file mandelbread.jas
+//! method 0x0000 0x0042 main
+//! method 0x0042 0x0080 foo
+//! local 0x0042 1 x
+//! label 0x0050 loop
+//! line 0x0050 312
+//! ```
+//!
+//! - `file <path>`: the source file, which may contain spaces. Optional.
+//! - `method <start> <end> <name>`: the method whose text is `start..end`. For anything but
+//!   main, `start` is the address of the method header.
+//! - `local <method start> <index> <name>`: the name of a local variable of a method.
+//! - `label <addr> <name>`: a label. Labels are scoped to the method they are in.
+//! - `line <addr> <line>`: the source line of the instruction at `addr`.
+//!
+//! Addresses include the text block's origin and are written in hex with a `0x` prefix,
+//! though decimal is accepted too. Lines starting with any other word are ignored, so the
+//! format can grow without breaking older readers.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::Word;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodInfo {
+    pub name: String,
+    pub start: Word,
+    pub end: Word,
+    /// Local variable names by index.
+    pub locals: BTreeMap<u16, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub file: Option<String>,
+    /// Sorted by start address.
+    pub methods: Vec<MethodInfo>,
+    /// Sorted by address. An address may have several labels, and since labels are scoped
+    /// to their method, a name may occur more than once too.
+    pub labels: Vec<(Word, String)>,
+    /// Source line of each instruction, by address.
+    pub lines: BTreeMap<Word, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugInfoError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl Display for DebugInfoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for DebugInfoError {}
+
+fn parse_number<T: TryFrom<i64>>(s: &str) -> Option<T> {
+    let val = match s.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    return T::try_from(val).ok();
+}
+
+impl DebugInfo {
+    pub fn parse(contents: &str) -> Result<DebugInfo, DebugInfoError> {
+        let mut info = DebugInfo::default();
+        let mut locals: Vec<(Word, u16, String)> = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let error = |msg: &str| DebugInfoError {
+                line: i + 1,
+                msg: format!("{msg}, found `{line}`."),
+            };
+            let line = line.trim();
+            if let Some(file) = line.strip_prefix("file ") {
+                info.file = Some(file.trim().to_string());
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["method", start, end, name] => {
+                    let (Some(start), Some(end)) = (parse_number(start), parse_number(end)) else {
+                        return Err(error("Expected `method <start> <end> <name>`"));
+                    };
+                    info.methods.push(MethodInfo {
+                        name: name.to_string(),
+                        start,
+                        end,
+                        locals: BTreeMap::new(),
+                    });
+                }
+                ["local", method, index, name] => {
+                    let (Some(method), Some(index)) = (parse_number(method), parse_number(index))
+                    else {
+                        return Err(error("Expected `local <method start> <index> <name>`"));
+                    };
+                    locals.push((method, index, name.to_string()));
+                }
+                ["label", addr, name] => {
+                    let Some(addr) = parse_number(addr) else {
+                        return Err(error("Expected `label <addr> <name>`"));
+                    };
+                    info.labels.push((addr, name.to_string()));
+                }
+                ["line", addr, line] => {
+                    let (Some(addr), Some(line)) = (parse_number(addr), parse_number(line)) else {
+                        return Err(error("Expected `line <addr> <line>`"));
+                    };
+                    info.lines.insert(addr, line);
+                }
+                ["method" | "local" | "label" | "line", ..] => {
+                    return Err(error("Wrong number of fields"));
+                }
+                _ => (),
+            }
+        }
+        info.methods.sort_by_key(|m| m.start);
+        info.labels.sort();
+        for (start, index, name) in locals {
+            let Some(method) = info.methods.iter_mut().find(|m| m.start == start) else {
+                return Err(DebugInfoError {
+                    line: 0,
+                    msg: format!("Local `{name}` belongs to an unknown method at {start:#06x}."),
+                });
+            };
+            method.locals.insert(index, name);
+        }
+        return Ok(info);
+    }
+
+    /// The labels at `addr`.
+    pub fn labels_at(&self, addr: Word) -> impl Iterator<Item = &str> {
+        let from = self.labels.partition_point(|(a, _)| *a < addr);
+        return self.labels[from..]
+            .iter()
+            .take_while(move |(a, _)| *a == addr)
+            .map(|(_, name)| name.as_str());
+    }
+
+    /// The method `pc` is in.
+    pub fn method_at(&self, pc: Word) -> Option<&MethodInfo> {
+        return self.methods.iter().find(|m| m.start <= pc && pc < m.end);
+    }
+
+    /// The source line of the instruction at `pc`, or of the closest one before it in the
+    /// same method.
+    pub fn line_at(&self, pc: Word) -> Option<usize> {
+        let from = self.method_at(pc).map_or(Word::MIN, |m| m.start);
+        return self
+            .lines
+            .range(from..=pc)
+            .next_back()
+            .map(|(_, &line)| line);
+    }
+
+    /// Name of local `index` of the method `pc` is in.
+    pub fn local_name(&self, pc: Word, index: u16) -> Option<&str> {
+        return self.method_at(pc)?.locals.get(&index).map(String::as_str);
+    }
+
+    /// E.g. `mandelbread.jas:312 in method foo`, or `None` if nothing is known about `pc`.
+    pub fn location(&self, pc: Word) -> Option<String> {
+        let line = self.line_at(pc).map(|line| match &self.file {
+            Some(file) => format!("{file}:{line}"),
+            None => format!("line {line}"),
+        });
+        let method = self.method_at(pc).map(|m| format!("method {}", m.name));
+        return match (line, method) {
+            (Some(line), Some(method)) => Some(format!("{line} in {method}")),
+            (Some(s), None) | (None, Some(s)) => Some(s),
+            (None, None) => None,
+        };
+    }
+
+    /// The address of the first instruction on `line`, or on the closest line after it.
+    pub fn addr_of_line(&self, line: usize) -> Option<Word> {
+        return self
+            .lines
+            .iter()
+            .filter(|(_, &l)| l >= line)
+            .min_by_key(|(&addr, &l)| (l, addr))
+            .map(|(&addr, _)| addr);
+    }
+}
+
+impl Display for DebugInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            writeln!(f, "file {file}")?;
+        }
+        for m in &self.methods {
+            writeln!(f, "method {:#06x} {:#06x} {}", m.start, m.end, m.name)?;
+            for (index, name) in &m.locals {
+                writeln!(f, "local {:#06x} {index} {name}", m.start)?;
+            }
+        }
+        for (addr, name) in &self.labels {
+            writeln!(f, "label {addr:#06x} {name}")?;
+        }
+        for (addr, line) in &self.lines {
+            writeln!(f, "line {addr:#06x} {line}")?;
+        }
+        return Ok(());
+    }
+}
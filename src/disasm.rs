@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::loader::IjvmFile;
+use crate::opcode::{Opcode, Operand};
+use crate::{Byte, Word};
+
+pub(crate) enum Item {
+    Instruction {
+        addr: usize,
+        wide: bool,
+        op: Opcode,
+        operands: Vec<(Operand, i32)>,
+    },
+    /// A byte that doesn't start a valid instruction.
+    Unknown { addr: usize, byte: Byte },
+}
+
+pub(crate) struct Region {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) is_main: bool,
+    pub(crate) num_args: u16,
+    pub(crate) num_vars: u16,
+    pub(crate) items: Vec<Item>,
+}
+
+/// A binary split into main and its methods.
+pub(crate) struct Decoded {
+    pub(crate) constants: Vec<Word>,
+    /// Constant pool index to method address, relative to the text block.
+    pub(crate) method_entries: BTreeMap<usize, usize>,
+    pub(crate) regions: Vec<Region>,
+}
+
+fn read_u16(text: &[Byte], addr: usize) -> u16 {
+    return u16::from_be_bytes([text[addr], text[addr + 1]]);
+}
+
+pub(crate) fn decode(text: &[Byte], addr: usize, end: usize) -> Item {
+    let unknown = Item::Unknown {
+        addr,
+        byte: text[addr],
+    };
+    let wide = text[addr] == Opcode::Wide.byte();
+    let op_addr = if wide { addr + 1 } else { addr };
+    if op_addr >= end {
+        return unknown;
+    }
+    let Some(op) = Opcode::from_byte(text[op_addr]) else {
+        return unknown;
+    };
+    if wide && !op.can_be_wide() {
+        return unknown;
+    }
+
+    let mut ptr = op_addr + 1;
+    let mut operands = Vec::new();
+    for kind in op.operands() {
+        let size = kind.size(wide);
+        if ptr + size > end {
+            return unknown;
+        }
+        let val = match (kind, size) {
+            (Operand::Byte, _) => text[ptr] as i8 as i32,
+            (_, 1) => text[ptr] as i32,
+            (Operand::Offset, _) => op_addr as i32 + read_u16(text, ptr) as i16 as i32,
+            _ => read_u16(text, ptr) as i32,
+        };
+        operands.push((*kind, val));
+        ptr += size;
+    }
+    return Item::Instruction {
+        addr,
+        wide,
+        op,
+        operands,
+    };
+}
+
+fn item_len(item: &Item) -> usize {
+    return match item {
+        Item::Unknown { .. } => 1,
+        Item::Instruction { wide, op, .. } => op.encoded_len(*wide) + if *wide { 1 } else { 0 },
+    };
+}
+
+/// Split the text into main and the methods starting at `methods`, and decode each.
+fn decode_regions(text: &[Byte], methods: &BTreeSet<usize>) -> Vec<Region> {
+    let mut starts: Vec<usize> = vec![0];
+    starts.extend(methods.iter().filter(|&&m| m != 0));
+    let mut regions = Vec::new();
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(text.len());
+        let is_main = i == 0;
+        let (num_args, num_vars, mut ptr) = if is_main || end - start < 4 {
+            (0, 0, start)
+        } else {
+            (read_u16(text, start), read_u16(text, start + 2), start + 4)
+        };
+        let mut items = Vec::new();
+        while ptr < end {
+            let item = decode(text, ptr, end);
+            ptr += item_len(&item);
+            items.push(item);
+        }
+        regions.push(Region {
+            start,
+            end,
+            is_main,
+            num_args,
+            num_vars,
+            items,
+        });
+    }
+    return regions;
+}
+
+fn local_name(region: &Region, main_vars: usize, index: i32) -> String {
+    let index = index as usize;
+    let num_args = region.num_args as usize;
+    return if region.is_main && index < main_vars {
+        format!("var{index}")
+    } else if !region.is_main && index >= 1 && index < num_args {
+        format!("arg{index}")
+    } else if !region.is_main && index >= num_args && index < num_args + region.num_vars as usize {
+        format!("var{}", index - num_args)
+    } else {
+        format!("{index}")
+    };
+}
+
+/// Find the methods through the constant pool entries that `INVOKEVIRTUAL` and `TAILCALL` use,
+/// decoding until no new ones show up.
+pub(crate) fn decode_program(program: &IjvmFile) -> Decoded {
+    let text = &program.text().data;
+    let origin = program.text().origin;
+    let constants: Vec<Word> = match program.constant_pool() {
+        Some(cp) => cp
+            .data
+            .chunks_exact(4)
+            .map(|c| Word::from_be_bytes([c[0], c[1], c[2], c[3]]))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let mut method_entries: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut regions;
+    loop {
+        let methods: BTreeSet<usize> = method_entries.values().copied().collect();
+        regions = decode_regions(text, &methods);
+        let mut found = false;
+        for item in regions.iter().flat_map(|r| &r.items) {
+            if let Item::Instruction {
+                op: Opcode::Invokevirtual | Opcode::Tailcall,
+                operands,
+                ..
+            } = item
+            {
+                let index = operands[0].1 as usize;
+                let Some(&target) = constants.get(index) else {
+                    continue;
+                };
+                let target = target.wrapping_sub(origin);
+                if target > 0 && (target as usize) < text.len() {
+                    found |= method_entries.insert(index, target as usize).is_none();
+                }
+            }
+        }
+        if !found {
+            break;
+        }
+    }
+    return Decoded {
+        constants,
+        method_entries,
+        regions,
+    };
+}
+
+/// One more than the highest local variable index the region's code uses. Main has no header,
+/// so this is all there is to tell how many variables it has.
+pub(crate) fn used_locals(region: &Region) -> usize {
+    return region
+        .items
+        .iter()
+        .flat_map(|item| match item {
+            Item::Instruction { operands, .. } => operands.clone(),
+            Item::Unknown { .. } => Vec::new(),
+        })
+        .filter(|(kind, _)| *kind == Operand::Var)
+        .map(|(_, val)| val as usize + 1)
+        .max()
+        .unwrap_or(0);
+}
+
+/// The names the disassembler gives methods, by start address relative to the text block.
+pub(crate) fn method_names(regions: &[Region]) -> BTreeMap<usize, String> {
+    let mut names = BTreeMap::new();
+    for (i, region) in regions.iter().skip(1).enumerate() {
+        names.insert(region.start, format!("method{}", i + 1));
+    }
+    return names;
+}
+
+/// Turn a binary back into JAS source.
+///
+/// Methods are found through the constant pool entries that `INVOKEVIRTUAL` and `TAILCALL` use.
+/// Anything that can't be expressed in JAS, such as stray bytes or jumps out of a method, is
+/// kept as a comment, so only binaries the assembler could have produced reassemble to the same
+/// bytes.
+pub fn disassemble(program: &IjvmFile) -> String {
+    let origin = program.text().origin;
+    let Decoded {
+        constants,
+        method_entries,
+        regions,
+    } = decode_program(program);
+
+    let method_names = method_names(&regions);
+    let method_name = |index: usize| -> Option<&String> {
+        return method_names.get(method_entries.get(&index)?);
+    };
+
+    let mut out = String::new();
+
+    // The assembler appends method addresses to the constant pool in text order.
+    let first_method = constants.len() - method_entries.len();
+    let in_order = method_entries
+        .keys()
+        .copied()
+        .eq(first_method..constants.len())
+        && method_entries
+            .values()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .all(|w| w[0] < w[1]);
+    if !in_order {
+        writeln!(
+            out,
+            "// Note: method addresses aren't at the end of the constant pool in text order,\n\
+             // so reassembling this will reorder the constant pool."
+        )
+        .unwrap();
+    }
+
+    writeln!(out, ".constant").unwrap();
+    for (i, c) in constants.iter().enumerate() {
+        if !method_entries.contains_key(&i) {
+            writeln!(out, "    CONST{i:<8} {c}").unwrap();
+        }
+    }
+    writeln!(out, ".end-constant").unwrap();
+
+    for region in &regions {
+        let mut labels: BTreeSet<usize> = BTreeSet::new();
+        let mut boundaries: BTreeSet<usize> = region
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Instruction { addr, .. } | Item::Unknown { addr, .. } => *addr,
+            })
+            .collect();
+        boundaries.insert(region.end);
+        for item in &region.items {
+            if let Item::Instruction { operands, .. } = item {
+                for (kind, val) in operands {
+                    if *kind == Operand::Offset && boundaries.contains(&(*val as usize)) {
+                        labels.insert(*val as usize);
+                    }
+                }
+            }
+        }
+
+        let main_vars = used_locals(region);
+
+        writeln!(out).unwrap();
+        if region.is_main {
+            writeln!(out, ".main").unwrap();
+        } else {
+            let args: Vec<String> = (1..region.num_args).map(|i| format!("arg{i}")).collect();
+            writeln!(
+                out,
+                ".method {}({})",
+                method_names[&region.start],
+                args.join(", ")
+            )
+            .unwrap();
+        }
+        let num_vars = if region.is_main {
+            main_vars
+        } else {
+            region.num_vars as usize
+        };
+        if num_vars > 0 {
+            writeln!(out, ".var").unwrap();
+            for i in 0..num_vars {
+                writeln!(out, "    var{i}").unwrap();
+            }
+            writeln!(out, ".end-var").unwrap();
+        }
+
+        for item in &region.items {
+            let (addr, line) = match item {
+                Item::Unknown { addr, byte } => (*addr, format!("// .byte {byte:#04x}")),
+                Item::Instruction {
+                    addr,
+                    wide,
+                    op,
+                    operands,
+                } => {
+                    let mut line = String::from(op.mnemonic());
+                    for (kind, val) in operands {
+                        let operand = match kind {
+                            Operand::Byte => format!("{val}"),
+                            Operand::Var => local_name(region, main_vars, *val),
+                            Operand::Offset if labels.contains(&(*val as usize)) => {
+                                format!("L{val}")
+                            }
+                            Operand::Offset => {
+                                format!("{val} // jumps outside of this method")
+                            }
+                            Operand::Constant if method_entries.contains_key(&(*val as usize)) => {
+                                format!("{val} // a method address")
+                            }
+                            Operand::Constant if (*val as usize) < constants.len() => {
+                                format!("CONST{val}")
+                            }
+                            Operand::Method => match method_name(*val as usize) {
+                                Some(name) => name.clone(),
+                                None => format!("{val} // not a method"),
+                            },
+                            Operand::Constant => format!("{val} // not in the constant pool"),
+                        };
+                        line = format!("{line} {operand}");
+                    }
+                    if *wide {
+                        writeln!(out, "    WIDE").unwrap();
+                    }
+                    (*addr, line)
+                }
+            };
+            if labels.contains(&addr) {
+                writeln!(out, "L{addr}:").unwrap();
+            }
+            if line.contains("//") {
+                writeln!(out, "    {line}").unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "    {line:<28} // {:#06x}",
+                    (addr as Word).wrapping_add(origin)
+                )
+                .unwrap();
+            }
+        }
+        if labels.contains(&region.end) {
+            writeln!(out, "L{}:", region.end).unwrap();
+        }
+        writeln!(
+            out,
+            "{}",
+            if region.is_main {
+                ".end-main"
+            } else {
+                ".end-method"
+            }
+        )
+        .unwrap();
+    }
+    return out;
+}
@@ -0,0 +1,126 @@
+use crate::machine::Machine;
+use crate::Word;
+
+/// One activation on the stack, seen from outside the machine.
+///
+/// A method's frame starts at LV, where `INVOKEVIRTUAL` replaces OBJREF with the link
+/// pointer. The arguments and variables follow, then the caller's PC, which the link pointer
+/// points to, and the caller's LV. The method's operand stack is above that:
+///
+/// ```text
+/// LV -> link pointer --+
+///       arguments      |
+///       variables      |
+///       caller's PC <--+
+///       caller's LV
+///       operands ...
+/// ```
+///
+/// `TAILCALL` reuses the frame at the same LV for the callee, keeping the caller's PC and LV.
+///
+/// Main's frame has the same shape, as if main had been called with OBJREF: it starts at 0,
+/// and its saved PC and LV are 0. Binaries number main's variables from 0, so its locals don't
+/// include the link pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Start address of the method including the text block's origin, which for anything
+    /// but main is its header.
+    pub method: Word,
+    /// The next instruction in this frame: the machine's PC for the innermost frame, and the
+    /// return address for the others.
+    pub pc: Word,
+    /// Where `IRETURN` continues, or `None` for main.
+    pub return_pc: Option<Word>,
+    pub lv: usize,
+    /// Local variables. In a method other than main, local 0 is the link pointer that replaced
+    /// OBJREF.
+    pub locals: &'a [Word],
+    /// Locals 1 up to the number the method header declares; empty for main.
+    pub args: &'a [Word],
+    /// The operand stack, bottom first.
+    pub operands: &'a [Word],
+}
+
+impl Frame<'_> {
+    pub fn is_main(&self) -> bool {
+        return self.return_pc.is_none();
+    }
+}
+
+/// The frames on the stack, innermost first. See [`Machine::frames`].
+pub struct Frames<'a> {
+    machine: &'a Machine,
+    /// Frames left to yield.
+    depth: usize,
+    lv: usize,
+    /// One past the top of the next frame's operand stack.
+    top: usize,
+    pc: Word,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(machine: &'a Machine) -> Frames<'a> {
+        return Frames {
+            machine,
+            depth: machine.methods.len(),
+            lv: machine.stack.lv,
+            top: machine.stack.sp + 1,
+            pc: machine.pc(),
+        };
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Frame<'a>> {
+        if self.depth == 0 {
+            return None;
+        }
+        self.depth -= 1;
+        let machine = self.machine;
+        let data = &machine.stack.data;
+        let lv = self.lv;
+        // A program that overwrites its link pointer leaves nothing sensible to walk.
+        let link_ptr = usize::try_from(*data.get(lv)?)
+            .ok()
+            .filter(|&l| l >= lv && l + 1 < data.len() && l < self.top)?;
+
+        let method = machine.methods[self.depth];
+        let is_main = self.depth == 0;
+        let (return_pc, caller_lv) = if is_main {
+            (None, 0)
+        } else {
+            (
+                Some(machine.text_origin.wrapping_add(data[link_ptr])),
+                data[link_ptr + 1] as usize,
+            )
+        };
+        let locals = &data[machine.stack.local_slot_in(lv, 0)..link_ptr];
+        let num_args = match method.wrapping_sub(machine.text_origin) as usize {
+            _ if is_main => 0,
+            start => match machine.text.get(start..start + 2) {
+                Some(header) => u16::from_be_bytes([header[0], header[1]]) as usize,
+                None => 0,
+            },
+        };
+        let args = locals.get(1..num_args).unwrap_or_default();
+        let operands = &data[(link_ptr + 2).min(self.top)..self.top];
+
+        let frame = Frame {
+            method,
+            pc: self.pc,
+            return_pc,
+            lv,
+            locals,
+            args,
+            operands,
+        };
+        self.top = lv;
+        self.lv = caller_lv;
+        if let Some(pc) = return_pc {
+            self.pc = pc;
+        }
+        return Some(frame);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::Word;
+
+/// Array references are tagged with this in their upper byte, so that they stand out from
+/// plain integers in stack dumps. The lower bits are the generation of the array's slot on
+/// the heap and the slot, e.g. `0x0a100003` for slot 3 in generation 1.
+pub const REF_TAG: Word = 0x0A00_0000;
+const REF_SLOT_MASK: Word = 0x000F_FFFF;
+const REF_GENERATION_SHIFT: u32 = 20;
+const REF_GENERATION_MASK: Word = 0xF;
+
+/// Most arrays that can exist at once, since each needs its own slot.
+///
+/// A slot holds arrays in 16 generations and is retired after that, so a program that creates
+/// arrays without end eventually runs out of slots.
+pub const MAX_ARRAYS: usize = REF_SLOT_MASK as usize + 1;
+
+/// What the garbage collector has done so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: u64,
+    pub arrays_freed: u64,
+    pub words_freed: u64,
+}
+
+impl GcStats {
+    pub fn bytes_freed(&self) -> u64 {
+        return self.words_freed * 4;
+    }
+}
+
+/// Why [`Heap::alloc`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AllocError {
+    /// The array would grow the heap beyond its limit.
+    Full,
+    /// [`MAX_ARRAYS`] arrays exist already.
+    OutOfSlots,
+}
+
+/// Arrays created by `NEWARRAY`, which live until a garbage collection finds nothing on the
+/// stack referring to them.
+pub struct Heap {
+    /// By slot.
+    pub(crate) arrays: BTreeMap<Word, Vec<Word>>,
+    /// The current generation of each slot used so far. Freeing an array bumps its slot's
+    /// generation, so that references to it stay invalid.
+    pub(crate) generations: Vec<Word>,
+    /// Slots of collected arrays, reused oldest first. A slot in its last generation isn't
+    /// put here, since reusing it would make old references valid again.
+    pub(crate) free_slots: VecDeque<Word>,
+    /// Words in all arrays together.
+    pub(crate) words: usize,
+    /// Most words `words` may grow to.
+    pub(crate) limit: usize,
+    /// Collect automatically once `words` would grow beyond this.
+    pub(crate) threshold: usize,
+    /// Where the next automatic collection happens, which grows with the live arrays so that
+    /// a heap full of them isn't collected over and over.
+    pub(crate) next_collection: usize,
+    pub(crate) stats: GcStats,
+}
+
+impl Heap {
+    pub(crate) fn new(limit: usize, threshold: usize) -> Heap {
+        return Heap {
+            arrays: BTreeMap::new(),
+            generations: Vec::new(),
+            free_slots: VecDeque::new(),
+            words: 0,
+            limit,
+            threshold,
+            next_collection: threshold,
+            stats: GcStats::default(),
+        };
+    }
+
+    /// Whether `word` looks like an array reference. It might still be an integer that happens
+    /// to have the tag, or refer to an array that doesn't exist (anymore).
+    pub fn is_ref(word: Word) -> bool {
+        return word >> 24 == REF_TAG >> 24;
+    }
+
+    /// The slot of the live array `reference` refers to, if any.
+    fn slot(&self, reference: Word) -> Option<Word> {
+        if !Heap::is_ref(reference) {
+            return None;
+        }
+        let slot = reference & REF_SLOT_MASK;
+        let generation = (reference >> REF_GENERATION_SHIFT) & REF_GENERATION_MASK;
+        return match self.generations.get(slot as usize) {
+            Some(&g) if g == generation => Some(slot),
+            _ => None,
+        };
+    }
+
+    fn reference(&self, slot: Word) -> Word {
+        return REF_TAG | self.generations[slot as usize] << REF_GENERATION_SHIFT | slot;
+    }
+
+    /// The array `reference` refers to, if any.
+    pub fn get(&self, reference: Word) -> Option<&[Word]> {
+        let slot = self.slot(reference)?;
+        return self.arrays.get(&slot).map(Vec::as_slice);
+    }
+
+    pub(crate) fn get_mut(&mut self, reference: Word) -> Option<&mut [Word]> {
+        let slot = self.slot(reference)?;
+        return self.arrays.get_mut(&slot).map(Vec::as_mut_slice);
+    }
+
+    /// References to all arrays and the arrays, by slot.
+    pub fn arrays(&self) -> impl Iterator<Item = (Word, &[Word])> {
+        return self
+            .arrays
+            .iter()
+            .map(|(&slot, array)| (self.reference(slot), array.as_slice()));
+    }
+
+    /// Number of arrays.
+    pub fn len(&self) -> usize {
+        return self.arrays.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.arrays.is_empty();
+    }
+
+    /// Words in all arrays together.
+    pub fn words(&self) -> usize {
+        return self.words;
+    }
+
+    pub fn stats(&self) -> GcStats {
+        return self.stats;
+    }
+
+    /// Whether allocating `len` more words should collect garbage first.
+    pub(crate) fn wants_collection(&self, len: usize) -> bool {
+        return self.words + len > self.next_collection.min(self.limit);
+    }
+
+    /// Create an array of `len` zeros and return a reference to it.
+    pub(crate) fn alloc(&mut self, len: usize) -> Result<Word, AllocError> {
+        if self.words + len > self.limit {
+            return Err(AllocError::Full);
+        }
+        let slot = match self.free_slots.pop_front() {
+            Some(slot) => slot,
+            None if self.generations.len() < MAX_ARRAYS => {
+                self.generations.push(0);
+                self.generations.len() as Word - 1
+            }
+            None => return Err(AllocError::OutOfSlots),
+        };
+        self.arrays.insert(slot, vec![0; len]);
+        self.words += len;
+        return Ok(self.reference(slot));
+    }
+
+    /// Free every array that neither `roots` nor an array reachable from them refers to.
+    /// Any word that looks like a reference counts, since the stack doesn't say which words
+    /// are references.
+    pub(crate) fn collect(&mut self, roots: &[Word]) {
+        let mut marked = BTreeMap::new();
+        let mut pending: Vec<Word> = roots.iter().copied().filter(|&w| Heap::is_ref(w)).collect();
+        while let Some(reference) = pending.pop() {
+            let Some(slot) = self.slot(reference) else {
+                continue;
+            };
+            let Some(array) = self.arrays.remove(&slot) else {
+                continue;
+            };
+            pending.extend(array.iter().copied().filter(|&w| Heap::is_ref(w)));
+            marked.insert(slot, array);
+        }
+
+        let freed = std::mem::replace(&mut self.arrays, marked);
+        for &slot in freed.keys() {
+            let generation = &mut self.generations[slot as usize];
+            if *generation < REF_GENERATION_MASK {
+                *generation += 1;
+                self.free_slots.push_back(slot);
+            }
+        }
+        let words_freed: usize = freed.values().map(Vec::len).sum();
+        self.words -= words_freed;
+        self.stats.collections += 1;
+        self.stats.arrays_freed += freed.len() as u64;
+        self.stats.words_freed += words_freed as u64;
+        self.next_collection = self.threshold.max(self.words * 2);
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::{Result, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::Byte;
+
+/// How IN treats the input stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputMode {
+    /// Push every byte unchanged, newlines included, and 0 once no input is available.
+    #[default]
+    Standard,
+    /// Push 0 for a newline and halt with an error at the end of the input, as older
+    /// versions of this emulator did.
+    Legacy,
+}
+
+/// An in-memory output that can still be read after the machine writing to it is gone.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<Byte>>>);
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        return SharedBuffer::default();
+    }
+
+    pub fn contents(&self) -> Vec<Byte> {
+        return self.0.lock().expect("writer didn't panic").clone();
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().expect("writer didn't panic").extend(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        return Ok(());
+    }
+}
@@ -0,0 +1,229 @@
+#![allow(clippy::needless_return, clippy::identity_op)]
+
+pub mod asm;
+pub mod debugger;
+pub mod debuginfo;
+pub mod disasm;
+pub mod frame;
+pub mod heap;
+pub mod io;
+pub mod loader;
+pub mod machine;
+pub mod match_op;
+pub mod net;
+pub mod opcode;
+pub mod report;
+pub mod stack;
+pub mod trace;
+pub mod verify;
+
+use std::fmt::Display;
+
+pub use debuginfo::DebugInfo;
+pub use frame::Frame;
+pub use heap::{GcStats, Heap};
+pub use loader::{IjvmFile, LoadError};
+pub use machine::{
+    run_with_input, HaltReason, Machine, MachineBuilder, DEFAULT_GC_THRESHOLD, DEFAULT_HEAP_LIMIT,
+    DEFAULT_STACK_LIMIT, MIN_STACK_LIMIT,
+};
+pub use net::Net;
+pub use opcode::Opcode;
+pub use report::Report;
+pub use stack::Stack;
+pub use trace::{HumanTracer, JsonTracer, NullTracer, TraceEvent, Tracer};
+pub use verify::{verify, VerifyError};
+
+pub type Word = i32;
+pub type Byte = u8;
+
+/// A fault that stops the machine. PCs are addresses, including the text block's origin.
+#[derive(Debug)]
+pub enum OpError {
+    /// `opcode` needed more values than the operand stack of the current frame holds.
+    StackUnderflow {
+        pc: Word,
+        opcode: Opcode,
+        /// Start address of the method whose frame ran dry.
+        method: Word,
+    },
+    InvalidOpcode {
+        pc: Word,
+        byte: Byte,
+    },
+    /// WIDE followed by something other than ILOAD, ISTORE or IINC.
+    InvalidWide {
+        pc: Word,
+        byte: Byte,
+    },
+    ConstantOutOfBounds {
+        pc: Word,
+        index: u16,
+        pool_len: usize,
+    },
+    /// The instruction at `from` jumped to, or read an opcode or operand at, `to`, which is
+    /// outside of the text.
+    PcOutOfBounds {
+        from: Word,
+        to: Word,
+        text_size: usize,
+    },
+    LocalOutOfFrame {
+        pc: Word,
+        index: u16,
+        locals: usize,
+    },
+    /// `ISTORE` or `IINC` on local 0 of a method, which is the link pointer to the caller's
+    /// saved PC and LV.
+    LinkPointerWrite {
+        pc: Word,
+        opcode: Opcode,
+    },
+    /// `INVOKEVIRTUAL` or `TAILCALL` of the method at `method`, whose header says it takes no
+    /// arguments, though OBJREF always counts as one.
+    MissingObjref {
+        pc: Word,
+        opcode: Opcode,
+        method: Word,
+    },
+    /// `IRETURN` in main, which has no caller to return to.
+    ReturnFromMain {
+        pc: Word,
+    },
+    StackOverflow {
+        pc: Word,
+        limit: usize,
+    },
+    /// `IDIV` or `IREM` with a divisor of 0.
+    DivideByZero {
+        pc: Word,
+        opcode: Opcode,
+    },
+    /// `NEWARRAY` with a negative size.
+    InvalidArraySize {
+        pc: Word,
+        size: Word,
+    },
+    /// `NEWARRAY` would grow the heap beyond its limit.
+    HeapExhausted {
+        pc: Word,
+        limit: usize,
+    },
+    /// `NEWARRAY` with no slot left for the array, since `limit` arrays are alive already or
+    /// the slots of collected ones have been retired.
+    TooManyArrays {
+        pc: Word,
+        limit: usize,
+    },
+    /// `IALOAD` or `IASTORE` on something that doesn't refer to an array.
+    InvalidReference {
+        pc: Word,
+        opcode: Opcode,
+        value: Word,
+    },
+    ArrayIndexOutOfBounds {
+        pc: Word,
+        opcode: Opcode,
+        index: Word,
+        len: usize,
+    },
+    Io(std::io::Error),
+}
+
+impl Display for OpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpError::StackUnderflow { pc, opcode, method } => write!(
+                f,
+                "Stack underflow at PC {pc:#x} in the method at {method:#x}: \
+                 {opcode} popped more values than the current frame holds"
+            ),
+            OpError::InvalidOpcode { pc, byte } => {
+                write!(f, "Invalid opcode {byte:#04x} at PC {pc:#x}")
+            }
+            OpError::InvalidWide { pc, byte } => write!(
+                f,
+                "WIDE at PC {pc:#x} followed by opcode {byte:#04x}, expected ILOAD, ISTORE or IINC"
+            ),
+            OpError::ConstantOutOfBounds {
+                pc,
+                index,
+                pool_len,
+            } => write!(
+                f,
+                "Constant {index} out of bounds at PC {pc:#x}, the constant pool has {pool_len} entries"
+            ),
+            OpError::PcOutOfBounds {
+                from,
+                to,
+                text_size,
+            } => write!(
+                f,
+                "PC out of bounds: from {from:#x} to {to:#x}, the text is {text_size} bytes long"
+            ),
+            OpError::LocalOutOfFrame { pc, index, locals } => write!(
+                f,
+                "Local variable {index} at PC {pc:#x} is outside of the current frame, which has {locals} locals"
+            ),
+            OpError::LinkPointerWrite { pc, opcode } => write!(
+                f,
+                "{opcode} at PC {pc:#x} on local variable 0, which holds the method's link pointer"
+            ),
+            OpError::MissingObjref { pc, opcode, method } => write!(
+                f,
+                "{opcode} at PC {pc:#x} calls the method at {method:#x}, whose header declares \
+                 no arguments, not even OBJREF"
+            ),
+            OpError::ReturnFromMain { pc } => {
+                write!(f, "IRETURN at PC {pc:#x} in main, which has no caller to return to")
+            }
+            OpError::StackOverflow { pc, limit } => write!(
+                f,
+                "Stack overflow at PC {pc:#x}: the stack is limited to {limit} words"
+            ),
+            OpError::DivideByZero { pc, opcode } => {
+                write!(f, "Division by zero at PC {pc:#x}: {opcode} with a divisor of 0")
+            }
+            OpError::InvalidArraySize { pc, size } => {
+                write!(f, "NEWARRAY at PC {pc:#x} with a negative size of {size}")
+            }
+            OpError::HeapExhausted { pc, limit } => write!(
+                f,
+                "Out of heap at PC {pc:#x}: the heap is limited to {limit} words"
+            ),
+            OpError::TooManyArrays { pc, limit } => write!(
+                f,
+                "Out of array references at PC {pc:#x}: at most {limit} arrays can exist at once"
+            ),
+            OpError::InvalidReference { pc, opcode, value } => write!(
+                f,
+                "{opcode} at PC {pc:#x} on {value:#x}, which isn't an array reference"
+            ),
+            OpError::ArrayIndexOutOfBounds {
+                pc,
+                opcode,
+                index,
+                len,
+            } => write!(
+                f,
+                "{opcode} at PC {pc:#x} with index {index} out of bounds for an array of {len} words"
+            ),
+            OpError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for OpError {
+    fn from(e: std::io::Error) -> Self {
+        OpError::Io(e)
+    }
+}
+
+impl std::error::Error for OpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            OpError::Io(e) => Some(e),
+            _ => None,
+        };
+    }
+}
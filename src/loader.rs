@@ -0,0 +1,277 @@
+use std::fmt::Display;
+use std::io;
+
+use crate::{Byte, Word};
+
+pub const MAGIC: Word = 0x1deadfad;
+pub const TEXT_ORIGIN: Word = 0x0;
+pub const CONSTANT_POOL_ORIGIN: Word = 0x10000;
+
+/// A block of an IJVM binary: a header of origin and size, followed by the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub origin: Word,
+    pub data: Vec<Byte>,
+}
+
+impl Block {
+    fn end(&self) -> u64 {
+        return self.origin as u32 as u64 + self.data.len() as u64;
+    }
+}
+
+/// A parsed IJVM binary: the magic number followed by any number of blocks.
+///
+/// The text block is the one at [`TEXT_ORIGIN`] and the constant pool the one at
+/// [`CONSTANT_POOL_ORIGIN`]. If only one of them is there, the first block at another
+/// origin is the other. If neither is, a lone block is the text, and of two blocks the
+/// constant pool is the one made of whole words. Anything else is
+/// [`LoadError::AmbiguousBlocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IjvmFile {
+    blocks: Vec<Block>,
+    text: usize,
+    constant_pool: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file ended while reading `expected` at `offset`.
+    Truncated {
+        offset: usize,
+        expected: String,
+        needed: usize,
+        available: usize,
+    },
+    BadMagic {
+        found: Word,
+    },
+    /// A block header at `offset` declares a size that can't be right.
+    InvalidSize {
+        offset: usize,
+        size: Word,
+    },
+    /// A block at `offset` would extend past the end of the 32 bit address space.
+    InvalidOrigin {
+        offset: usize,
+        origin: Word,
+        size: Word,
+    },
+    /// Two blocks claim the same addresses.
+    OverlappingBlocks {
+        first: Word,
+        second: Word,
+    },
+    MissingText,
+    /// None of the blocks is at a standard origin, and their contents don't tell which is
+    /// the text and which the constant pool.
+    AmbiguousBlocks {
+        origins: Vec<Word>,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Truncated {
+                offset,
+                expected,
+                needed,
+                available,
+            } => write!(
+                f,
+                "At byte offset {offset:#x}: expected {expected} ({needed} bytes), \
+                 but only {available} bytes are left in the file"
+            ),
+            LoadError::BadMagic { found } => write!(
+                f,
+                "At byte offset 0x0: expected magic number {MAGIC:#010x}, found {found:#010x}"
+            ),
+            LoadError::InvalidSize { offset, size } => write!(
+                f,
+                "At byte offset {offset:#x}: expected a non-negative block size, found {size}"
+            ),
+            LoadError::InvalidOrigin {
+                offset,
+                origin,
+                size,
+            } => write!(
+                f,
+                "At byte offset {offset:#x}: block origin {origin:#010x} with size {size:#x} \
+                 doesn't fit in the address space"
+            ),
+            LoadError::OverlappingBlocks { first, second } => write!(
+                f,
+                "Blocks at origins {first:#010x} and {second:#010x} overlap"
+            ),
+            LoadError::MissingText => write!(f, "Expected a text block, found none"),
+            LoadError::AmbiguousBlocks { origins } => {
+                let origins: Vec<String> = origins.iter().map(|o| format!("{o:#010x}")).collect();
+                write!(
+                    f,
+                    "Can't tell the text from the constant pool among the blocks at origins {}, \
+                     put the text at {TEXT_ORIGIN:#010x} or the constant pool at \
+                     {CONSTANT_POOL_ORIGIN:#010x}",
+                    origins.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+struct Reader<'a> {
+    buf: &'a [Byte],
+    ptr: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize, expected: &str) -> Result<&[Byte], LoadError> {
+        let available = self.buf.len() - self.ptr;
+        if len > available {
+            return Err(LoadError::Truncated {
+                offset: self.ptr,
+                expected: String::from(expected),
+                needed: len,
+                available,
+            });
+        }
+        let res = &self.buf[self.ptr..self.ptr + len];
+        self.ptr += len;
+        return Ok(res);
+    }
+
+    fn big_endian_word(&mut self, expected: &str) -> Result<Word, LoadError> {
+        let b = self.take(4, expected)?;
+        return Ok(((b[3] as Word) << 0)
+            | ((b[2] as Word) << 8)
+            | ((b[1] as Word) << 16)
+            | ((b[0] as Word) << 24));
+    }
+
+    fn block(&mut self) -> Result<Block, LoadError> {
+        let header = self.ptr;
+        let origin = self.big_endian_word("block origin")?;
+        let size = self.big_endian_word("block size")?;
+        if size < 0 {
+            return Err(LoadError::InvalidSize {
+                offset: header + 4,
+                size,
+            });
+        }
+        // A block may end exactly at the end of the address space.
+        if origin as u32 as u64 + size as u64 > 1 << 32 {
+            return Err(LoadError::InvalidOrigin {
+                offset: header,
+                origin,
+                size,
+            });
+        }
+        let data = Vec::from(self.take(size as usize, "block data")?);
+        return Ok(Block { origin, data });
+    }
+}
+
+impl IjvmFile {
+    /// Identify the text and constant pool among `blocks`.
+    pub fn new(blocks: Vec<Block>) -> Result<IjvmFile, LoadError> {
+        for (i, a) in blocks.iter().enumerate() {
+            for b in &blocks[i + 1..] {
+                let a_origin = a.origin as u32 as u64;
+                let b_origin = b.origin as u32 as u64;
+                if a_origin < b.end() && b_origin < a.end() {
+                    return Err(LoadError::OverlappingBlocks {
+                        first: a.origin,
+                        second: b.origin,
+                    });
+                }
+            }
+        }
+
+        let by_origin = |origin| blocks.iter().position(|b| b.origin == origin);
+        let fixed_text = by_origin(TEXT_ORIGIN);
+        let fixed_constant_pool = by_origin(CONSTANT_POOL_ORIGIN);
+        let mut unclaimed = (0..blocks.len())
+            .filter(|&i| Some(i) != fixed_text && Some(i) != fixed_constant_pool)
+            .collect::<Vec<usize>>()
+            .into_iter();
+        // Only the constant pool has to be made of whole words.
+        let words: Vec<bool> = blocks.iter().map(|b| b.data.len() % 4 == 0).collect();
+        let (text, constant_pool) = match (fixed_text, fixed_constant_pool, &words[..]) {
+            // A lone block is the text, not an empty program with constants.
+            (None, None, [] | [_]) => (unclaimed.next(), None),
+            // The order of the blocks in the file says nothing.
+            (None, None, [true, false]) => (Some(1), Some(0)),
+            (None, None, [false, true]) => (Some(0), Some(1)),
+            (None, None, _) => {
+                return Err(LoadError::AmbiguousBlocks {
+                    origins: blocks.iter().map(|b| b.origin).collect(),
+                })
+            }
+            (text, None, _) => (text, unclaimed.next()),
+            (None, cp, _) => (unclaimed.next(), cp),
+            (text, cp, _) => (text, cp),
+        };
+
+        return match text {
+            Some(text) => Ok(IjvmFile {
+                blocks,
+                text,
+                constant_pool,
+            }),
+            None => Err(LoadError::MissingText),
+        };
+    }
+
+    pub fn parse(contents: &[Byte]) -> Result<IjvmFile, LoadError> {
+        let mut reader = Reader {
+            buf: contents,
+            ptr: 0,
+        };
+
+        let magic = reader.big_endian_word("magic number")?;
+        if magic != MAGIC {
+            return Err(LoadError::BadMagic { found: magic });
+        }
+
+        let mut blocks = Vec::new();
+        while reader.ptr < contents.len() {
+            blocks.push(reader.block()?);
+        }
+
+        return IjvmFile::new(blocks);
+    }
+
+    /// Serialise back into the binary format, blocks in their original order.
+    pub fn to_bytes(&self) -> Vec<Byte> {
+        let mut out = Vec::from(MAGIC.to_be_bytes());
+        for block in &self.blocks {
+            out.extend(block.origin.to_be_bytes());
+            out.extend((block.data.len() as Word).to_be_bytes());
+            out.extend(&block.data);
+        }
+        return out;
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        return &self.blocks;
+    }
+
+    pub fn text(&self) -> &Block {
+        return &self.blocks[self.text];
+    }
+
+    /// The constant pool, or `None` if the program has no constants.
+    pub fn constant_pool(&self) -> Option<&Block> {
+        return self.constant_pool.map(|i| &self.blocks[i]);
+    }
+}
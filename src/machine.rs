@@ -0,0 +1,480 @@
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::disasm::{decode, decode_program, used_locals, Item};
+use crate::frame::Frames;
+use crate::heap::Heap;
+use crate::io::{InputMode, SharedBuffer};
+use crate::loader::{IjvmFile, LoadError};
+use crate::match_op::do_op;
+use crate::net::Net;
+use crate::opcode::{InstructionSet, Opcode, Operand};
+use crate::report::Report;
+use crate::stack::{Stack, MAIN_LV};
+use crate::trace::{TraceEvent, Tracer};
+use crate::{Byte, OpError, Word};
+
+pub struct Machine {
+    pub(crate) stack: Stack,
+    pub(crate) heap: Heap,
+    pub(crate) net: Net,
+    pub(crate) pc: i32,
+    pub(crate) text: Vec<Byte>,
+    pub(crate) text_origin: Word,
+    pub(crate) text_size: Word,
+    pub(crate) constant_pool: Vec<Byte>,
+    /// Address of the instruction being executed, relative to the text block.
+    pub(crate) op_pc: i32,
+    pub(crate) halt: Option<HaltReason>,
+    /// Whether the extensions such as `IMUL`, `NEWARRAY` and `TAILCALL` may run.
+    pub(crate) extended: bool,
+    /// Whether the network instructions such as `NETBIND` may run.
+    pub(crate) network: bool,
+    /// Start address of the method each active frame belongs to, main's first.
+    pub(crate) methods: Vec<Word>,
+    /// Instructions executed so far.
+    pub(crate) steps: u64,
+    pub(crate) input: Box<dyn Read + Send>,
+    pub(crate) input_mode: InputMode,
+    pub(crate) output: Box<dyn Write + Send>,
+    pub(crate) tracer: Option<Box<dyn Tracer + Send>>,
+}
+
+/// Why a [`Machine`] stopped.
+#[derive(Debug)]
+pub enum HaltReason {
+    Halt,
+    Err,
+    EndOfText,
+    Fault(OpError),
+}
+
+impl HaltReason {
+    /// Whether the program stopped the way it intended to, by HALT or by running off the end.
+    pub fn is_success(&self) -> bool {
+        return matches!(self, HaltReason::Halt | HaltReason::EndOfText);
+    }
+}
+
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::Halt => write!(f, "HALT reached."),
+            HaltReason::Err => write!(f, "ERR reached."),
+            HaltReason::EndOfText => write!(f, "End of text reached."),
+            HaltReason::Fault(e) => write!(f, "Error: {e}."),
+        }
+    }
+}
+
+/// Configures a [`Machine`] before it starts, e.g. to read input from somewhere other than stdin.
+pub struct MachineBuilder {
+    program: IjvmFile,
+    input: Box<dyn Read + Send>,
+    input_mode: InputMode,
+    output: Box<dyn Write + Send>,
+    stack_limit: usize,
+    heap_limit: usize,
+    gc_threshold: usize,
+    main_vars: Option<usize>,
+    extended: bool,
+    network: bool,
+    tracer: Option<Box<dyn Tracer + Send>>,
+}
+
+const MB: usize = 262144; // number of words in a MB is 2^20 / 4
+const INITIAL_STACK_SIZE: usize = 1024;
+/// Default for [`MachineBuilder::stack_limit`], in words.
+pub const DEFAULT_STACK_LIMIT: usize = 64 * MB;
+/// Smallest [`MachineBuilder::stack_limit`], in words: main's link pointer, saved PC and
+/// saved LV.
+pub const MIN_STACK_LIMIT: usize = MAIN_LV + 3;
+/// Default for [`MachineBuilder::heap_limit`], in words.
+pub const DEFAULT_HEAP_LIMIT: usize = 64 * MB;
+/// Default for [`MachineBuilder::gc_threshold`], in words.
+pub const DEFAULT_GC_THRESHOLD: usize = MB;
+
+impl MachineBuilder {
+    /// Where IN reads from. Defaults to stdin.
+    pub fn input<R: Read + Send + 'static>(mut self, input: R) -> MachineBuilder {
+        self.input = Box::new(input);
+        return self;
+    }
+
+    pub fn input_mode(mut self, mode: InputMode) -> MachineBuilder {
+        self.input_mode = mode;
+        return self;
+    }
+
+    /// Where OUT writes to. Defaults to stdout.
+    pub fn output<W: Write + Send + 'static>(mut self, output: W) -> MachineBuilder {
+        self.output = Box::new(output);
+        return self;
+    }
+
+    /// Most words the stack may grow to before a push faults with `StackOverflow`.
+    /// Defaults to [`DEFAULT_STACK_LIMIT`], i.e. 64 MB. At least [`MIN_STACK_LIMIT`] words are
+    /// always allowed, which main's frame needs even without variables.
+    pub fn stack_limit(mut self, words: usize) -> MachineBuilder {
+        self.stack_limit = words.max(MIN_STACK_LIMIT);
+        return self;
+    }
+
+    /// Most words all arrays together may take up before `NEWARRAY` faults with
+    /// `HeapExhausted`. Defaults to [`DEFAULT_HEAP_LIMIT`], i.e. 64 MB.
+    pub fn heap_limit(mut self, words: usize) -> MachineBuilder {
+        self.heap_limit = words;
+        return self;
+    }
+
+    /// How many words of arrays there may be before `NEWARRAY` collects garbage by itself.
+    /// Defaults to [`DEFAULT_GC_THRESHOLD`], i.e. 1 MB.
+    pub fn gc_threshold(mut self, words: usize) -> MachineBuilder {
+        self.gc_threshold = words;
+        return self;
+    }
+
+    /// Number of local variables main gets. By default just enough for the highest index
+    /// main's code uses, since binaries don't record it.
+    pub fn main_vars(mut self, vars: usize) -> MachineBuilder {
+        self.main_vars = Some(vars);
+        return self;
+    }
+
+    /// Allow the instructions beyond standard IJVM, which are invalid opcodes by default:
+    ///
+    /// - arithmetic: `IMUL`, `IDIV`, `IREM`, `INEG`, `SHL`, `SHR`, `USHR` and `IXOR`
+    /// - arrays: `NEWARRAY`, `IALOAD`, `IASTORE` and `GC`
+    /// - tail calls: `TAILCALL`
+    ///
+    /// The network instructions need [`MachineBuilder::network`] instead.
+    pub fn extended(mut self, extended: bool) -> MachineBuilder {
+        self.extended = extended;
+        return self;
+    }
+
+    /// Allow TCP connections to localhost with `NETBIND`, `NETCONNECT`, `NETIN`, `NETOUT` and
+    /// `NETCLOSE`. Off by default, where they are invalid opcodes.
+    pub fn network(mut self, network: bool) -> MachineBuilder {
+        self.network = network;
+        return self;
+    }
+
+    /// Gets told about every instruction executed. Without one, nothing is recorded.
+    pub fn tracer<T: Tracer + Send + 'static>(mut self, tracer: T) -> MachineBuilder {
+        self.tracer = Some(Box::new(tracer));
+        return self;
+    }
+
+    pub fn build(self) -> Machine {
+        let program = self.program;
+        let text = program.text();
+        let main_vars = match self.main_vars {
+            Some(vars) => vars,
+            None => used_locals(&decode_program(&program).regions[0]),
+        };
+        // Main's frame is laid out like a method's, as if main had been called with OBJREF and
+        // its variables as locals 1 and up. Its saved PC and LV stay 0.
+        let link_ptr = (MAIN_LV + 1).checked_add(main_vars);
+        let mut machine = Machine {
+            text: text.data.clone(),
+            text_origin: text.origin,
+            text_size: text.data.len() as Word,
+            pc: 0,
+            stack: Stack {
+                data: vec![0; INITIAL_STACK_SIZE], // TODO: keep track of which LV's have been stored?!
+                lv: MAIN_LV,
+                sp: MAIN_LV + 2,
+                limit: self.stack_limit,
+            },
+            heap: Heap::new(self.heap_limit, self.gc_threshold),
+            net: Net::default(),
+            constant_pool: match program.constant_pool() {
+                Some(cp) => cp.data.clone(),
+                None => Vec::new(),
+            },
+            op_pc: 0,
+            halt: None,
+            extended: self.extended,
+            network: self.network,
+            methods: vec![text.origin],
+            steps: 0,
+            input: self.input,
+            input_mode: self.input_mode,
+            output: self.output,
+            tracer: self.tracer,
+        };
+
+        let Some(link_ptr) = link_ptr.filter(|&l| machine.stack.reserve(l + 1)) else {
+            // Leave a frame without variables behind, so that the stack still makes sense.
+            // The stack limit always leaves room for it.
+            machine.stack.data[MAIN_LV] = MAIN_LV as Word + 1;
+            machine.halt = Some(HaltReason::Fault(OpError::StackOverflow {
+                pc: text.origin,
+                limit: machine.stack.limit,
+            }));
+            return machine;
+        };
+        machine.stack.sp = link_ptr + 1;
+        machine.stack.data[MAIN_LV] = link_ptr as Word;
+
+        return machine;
+    }
+}
+
+/// Run a binary to completion on the given input and return everything it printed.
+pub fn run_with_input(binary: &[Byte], input: &[Byte]) -> Result<Vec<Byte>, LoadError> {
+    let output = SharedBuffer::new();
+    let mut machine = Machine::builder(IjvmFile::parse(binary)?)
+        .input(io::Cursor::new(Vec::from(input)))
+        .output(output.clone())
+        .build();
+    machine.run();
+    return Ok(output.contents());
+}
+
+impl Machine {
+    pub fn builder(program: IjvmFile) -> MachineBuilder {
+        return MachineBuilder {
+            program,
+            input: Box::new(io::stdin()),
+            input_mode: InputMode::default(),
+            output: Box::new(io::stdout()),
+            stack_limit: DEFAULT_STACK_LIMIT,
+            heap_limit: DEFAULT_HEAP_LIMIT,
+            gc_threshold: DEFAULT_GC_THRESHOLD,
+            main_vars: None,
+            extended: false,
+            network: false,
+            tracer: None,
+        };
+    }
+
+    /// Set up a machine ready to run the given program, using stdin and stdout.
+    pub fn new(program: IjvmFile) -> Machine {
+        return Machine::builder(program).build();
+    }
+
+    /// Parse and load an IJVM binary from memory.
+    pub fn from_bytes(contents: &[Byte]) -> Result<Machine, LoadError> {
+        return Ok(Machine::new(IjvmFile::parse(contents)?));
+    }
+
+    /// Read and load an IJVM binary from a file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Machine, LoadError> {
+        let contents: Vec<Byte> = fs::read(path)?;
+        return Machine::from_bytes(&contents);
+    }
+
+    /// The address of the next instruction, including the text block's origin.
+    pub fn pc(&self) -> i32 {
+        return self.text_origin.wrapping_add(self.pc);
+    }
+
+    pub fn sp(&self) -> usize {
+        return self.stack.sp;
+    }
+
+    pub fn lv(&self) -> usize {
+        return self.stack.lv;
+    }
+
+    pub fn stack(&self) -> &Stack {
+        return &self.stack;
+    }
+
+    /// The arrays created so far.
+    pub fn heap(&self) -> &Heap {
+        return &self.heap;
+    }
+
+    /// The connections the program has open.
+    pub fn net(&self) -> &Net {
+        return &self.net;
+    }
+
+    /// Free the arrays that nothing on the stack, which includes every frame's locals, refers
+    /// to, directly or through other arrays. `GC` does this, and so does `NEWARRAY` when the
+    /// heap passes its threshold.
+    pub fn collect_garbage(&mut self) {
+        self.heap.collect(self.stack.as_slice());
+    }
+
+    /// The frames on the stack, from the current method's out to main's.
+    pub fn frames(&self) -> Frames<'_> {
+        return Frames::new(self);
+    }
+
+    /// Why and where the machine stopped, with a backtrace, for printing after a fault or ERR.
+    pub fn report(&self) -> Report<'_> {
+        return Report::new(self);
+    }
+
+    /// Number of active frames, 1 while in main.
+    pub fn call_depth(&self) -> usize {
+        return self.methods.len();
+    }
+
+    /// Entry `index` of the constant pool.
+    pub fn constant(&self, index: u16) -> Option<Word> {
+        let i = index as usize * 4;
+        let bytes = self.constant_pool.get(i..i + 4)?;
+        return Some(Word::from_be_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3],
+        ]));
+    }
+
+    /// Instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        return self.steps;
+    }
+
+    /// The instruction `step` will execute next, if the PC is at a valid opcode.
+    pub fn next_opcode(&self) -> Option<Opcode> {
+        return Opcode::from_byte(*self.text.get(self.pc as usize)?);
+    }
+
+    pub fn halted(&self) -> bool {
+        return self.halt.is_some();
+    }
+
+    /// Why the machine halted, or `None` while it is still running.
+    pub fn halt_reason(&self) -> Option<&HaltReason> {
+        return self.halt.as_ref();
+    }
+
+    /// Address of the instruction being executed, or last executed once the machine halted,
+    /// including the text block's origin.
+    pub fn op_addr(&self) -> Word {
+        return self.text_origin.wrapping_add(self.op_pc);
+    }
+
+    /// Start address of the method being executed, including the text block's origin.
+    pub fn method_addr(&self) -> Word {
+        return *self.methods.last().unwrap_or(&self.text_origin);
+    }
+
+    /// The text byte at `pc`, relative to the text block. Reading outside of the text is a
+    /// `PcOutOfBounds` fault of the current instruction.
+    pub(crate) fn text_byte(&self, pc: i32) -> Result<Byte, OpError> {
+        return match usize::try_from(pc).ok().and_then(|i| self.text.get(i)) {
+            Some(&byte) => Ok(byte),
+            None => Err(OpError::PcOutOfBounds {
+                from: self.op_addr(),
+                to: self.text_origin.wrapping_add(pc),
+                text_size: self.text.len(),
+            }),
+        };
+    }
+
+    /// The big-endian 16-bit text word at `pc`, checked like [`Machine::text_byte`].
+    pub(crate) fn text_u16(&self, pc: i32) -> Result<u16, OpError> {
+        return Ok(u16::from_be_bytes([
+            self.text_byte(pc)?,
+            self.text_byte(pc.wrapping_add(1))?,
+        ]));
+    }
+
+    fn supports(&self, set: InstructionSet) -> bool {
+        return match set {
+            InstructionSet::Standard => true,
+            InstructionSet::Extended => self.extended,
+            InstructionSet::Network => self.network,
+        };
+    }
+
+    /// Write out what the tracer buffered, e.g. before reporting how the machine stopped.
+    pub fn flush_tracer(&mut self) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.flush();
+        }
+    }
+
+    /// Run until the machine halts.
+    pub fn run(&mut self) {
+        while self.halt.is_none() {
+            self.step();
+        }
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) {
+        // Only an empty text gets here at its end, the other ways to get there stop below.
+        if self.pc == self.text_size {
+            self.halt = Some(HaltReason::EndOfText);
+            return;
+        }
+        self.op_pc = self.pc;
+        self.steps += 1;
+        // Copying the stack is only worth it if someone is watching.
+        let stack_before = self.tracer.as_ref().map(|_| self.stack.operands().to_vec());
+
+        let result = self.text_byte(self.pc).and_then(|cur_op| {
+            self.pc += 1;
+            return match Opcode::from_byte(cur_op) {
+                Some(op) if self.supports(op.info().set) => do_op(op, self),
+                _ => Err(OpError::InvalidOpcode {
+                    pc: self.op_addr(),
+                    byte: cur_op,
+                }),
+            };
+        });
+        if let Err(e) = result {
+            self.halt = Some(HaltReason::Fault(e));
+        }
+
+        // Falling or jumping to the very end of the text stops the program, but going beyond it
+        // would fetch garbage.
+        if self.halt.is_none() && self.pc == self.text_size {
+            self.halt = Some(HaltReason::EndOfText);
+        } else if self.halt.is_none() && (self.pc < 0 || self.pc > self.text_size) {
+            self.halt = Some(HaltReason::Fault(OpError::PcOutOfBounds {
+                from: self.op_addr(),
+                to: self.pc(),
+                text_size: self.text.len(),
+            }));
+        }
+
+        if let Some(stack_before) = stack_before {
+            self.trace(&stack_before);
+        }
+    }
+
+    fn trace(&mut self, stack_before: &[Word]) {
+        if self.op_pc < 0 || self.op_pc >= self.text_size {
+            return;
+        }
+        let byte = self.text[self.op_pc as usize];
+        // Bytes that don't decode still get an event, since they stopped the machine.
+        let (wide, op, operands) = match decode(&self.text, self.op_pc as usize, self.text.len()) {
+            Item::Instruction {
+                wide, op, operands, ..
+            } => (wide, Some(op), operands),
+            Item::Unknown { .. } => (false, None, Vec::new()),
+        };
+        let operands: Vec<Word> = operands
+            .iter()
+            .map(|&(kind, val)| match kind {
+                Operand::Offset => self.text_origin.wrapping_add(val),
+                _ => val,
+            })
+            .collect();
+        let pc = self.op_addr();
+        let Some(tracer) = self.tracer.as_mut() else {
+            return;
+        };
+        tracer.trace(&TraceEvent {
+            step: self.steps,
+            pc,
+            byte,
+            op,
+            wide,
+            operands: &operands,
+            stack_before,
+            stack_after: self.stack.operands(),
+            halt: self.halt.as_ref(),
+        });
+    }
+}
@@ -1,339 +1,549 @@
-pub mod match_op;
-use crate::match_op::*;
+#![allow(clippy::needless_return)]
 
+use std::collections::HashMap;
 use std::env;
-use std::fmt::Display;
-use std::fs::{self};
-use std::ops::{Index, IndexMut};
-
-use debug_print::{debug_eprint as deprint, debug_eprintln as deprintln};
-
-type Word = i32;
-type Byte = u8;
-
-pub struct Stack {
-    data: Vec<Word>,
-    sp: usize,
-    lv: usize,
-}
-
-impl Stack {
-    fn pop(&mut self) -> Result<Word, OpError> {
-        if self.is_empty() {
-            deprint!("\t\tWARN: Popping from empty stack!")
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use ijvrust::asm::assemble_with_debug_info;
+use ijvrust::debugger::Debugger;
+use ijvrust::disasm::disassemble;
+use ijvrust::io::InputMode;
+use ijvrust::{
+    verify, DebugInfo, HaltReason, HumanTracer, IjvmFile, JsonTracer, LoadError, Machine, Opcode,
+    DEFAULT_GC_THRESHOLD, DEFAULT_HEAP_LIMIT, DEFAULT_STACK_LIMIT, MIN_STACK_LIMIT,
+};
+
+const USAGE: &str = "\
+Usage: ijvrust [run] [options] <program.ijvm>
+       ijvrust trace [options] <program.ijvm>
+       ijvrust debug [options] <program.ijvm>
+       ijvrust stats [options] <program.ijvm>
+       ijvrust verify <program.ijvm>
+       ijvrust asm <input.jas> [-o <output.ijvm>] [-g]
+       ijvrust disasm <input.ijvm> [-o <output.jas>]
+
+Options:
+    -i, --input <file>        Read the program's input from <file> instead of stdin
+    -s, --input-string <str>  Use <str> as the program's input
+    -o, --output <file>       Write the program's output to <file> instead of stdout
+    -n, --max-steps <n>       Stop after executing <n> instructions
+        --debug-info <file>   Name source lines, methods and locals in traces, reports and the
+                              debugger; defaults to foo.dbg next to foo.ijvm if it exists
+    -t, --trace <format>      Print every instruction executed to stderr, where <format> is
+                              human, json (one object per line) or none (the default, except
+                              for trace)
+        --stack-size <words>  Most words the stack may grow to, at least 3 (default 64 MB worth)
+        --main-vars <n>       Give main <n> local variables instead of as many as its code uses
+        --heap-size <words>   Most words all arrays together may take up (default 64 MB worth)
+        --gc-threshold <words>
+                              Collect garbage when arrays take up more than this (default 1 MB
+                              worth), as well as on GC
+    -x, --extended            Allow the instructions beyond standard IJVM:
+                                arithmetic  IMUL IDIV IREM INEG SHL SHR USHR IXOR
+                                arrays      NEWARRAY IALOAD IASTORE GC
+                                tail calls  TAILCALL
+        --net                 Allow TCP connections to localhost:
+                                network     NETBIND NETCONNECT NETIN NETOUT NETCLOSE
+        --legacy-in           Make IN push 0 for a newline, like the original emulator
+    -v, --verbose             Always report how and where the program stopped
+    -q, --quiet               Never report how the program stopped
+
+Exit status: 0 after HALT or running off the end of the text, 1 after ERR, 2 after a fault,
+3 when --max-steps ran out, 4 if the program couldn't be loaded or the arguments are wrong.
+Quitting the debugger before the program stops exits with 0. asm, disasm and verify exit
+with 1 if the source doesn't assemble, the binary has problems or the output can't be
+written, and with 4 like the others if the input can't be read or the arguments are wrong.";
+
+const EXIT_ERR: u8 = 1;
+const EXIT_FAULT: u8 = 2;
+const EXIT_STEP_LIMIT: u8 = 3;
+const EXIT_USAGE: u8 = 4;
+
+/// `ijvrust asm foo.jas [-o foo.ijvm] [-g]`, where `-g` also writes `foo.dbg`.
+fn asm(args: &[String]) -> ExitCode {
+    let usage = || {
+        eprintln!("Usage: ijvrust asm <input.jas> [-o <output.ijvm>] [-g]");
+        return ExitCode::from(EXIT_USAGE);
+    };
+    let mut input = None;
+    let mut output = None;
+    let mut debug_info = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(o) => output = Some(PathBuf::from(o)),
+                None => return usage(),
+            },
+            "-g" => debug_info = true,
+            _ if input.is_none() && !arg.starts_with('-') => input = Some(arg),
+            _ => return usage(),
         }
-        let ret = self.top();
-        self.sp -= 1;
-        return ret;
     }
+    let Some(input) = input else {
+        return usage();
+    };
+    let output = output.unwrap_or_else(|| PathBuf::from(input).with_extension("ijvm"));
 
-    /*
-     * LV points to link ptr,
-     * link ptr points to caller's PC,
-     * above caller's PC is caller's LV,
-     * above caller's LV is callee's stack.
-     */
-    fn is_empty(&self) -> bool {
-        let link_ptr = self.data[self.lv];
-        let cond = self.sp <= link_ptr as usize + 1;
-        if self.sp < link_ptr as usize + 1 {
-            deprint!("\t\tWARN: SP below LINK PTR + 1!")
+    let source = match fs::read_to_string(input) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Couldn't read {input}: {e}");
+            return ExitCode::from(EXIT_USAGE);
         }
-
-        return cond;
-    }
-
-    fn top(&self) -> Result<Word, OpError> {
-        return Ok(self.data[self.sp]);
-        // return if self.sp >= (self.data[self.lv] as usize) {
-        //     //TODO: maybe self.data[self.data[self.lv]]?
-        //     Ok(self.data[self.sp])
-        // } else {
-        //     Err(OpError::EmptyStackError(()))
-        // };
-    }
-
-    fn push(&mut self, val: Word) {
-        self.sp += 1;
-        self.data[self.sp] = val;
-    }
-
-    fn _eprint(&mut self) {
-        deprint!(
-            "\tStack: SP={} LV={} LINK_PTR={} [",
-            self.sp,
-            self.lv,
-            self.data[self.lv]
-        );
-        if self.sp <= self.data[self.lv] as usize + 1 {
-            deprint!("]");
-            if self.sp < self.data[self.lv] as usize + 1 {
-                deprint!("\t\tWARN: SP below LINK PTR + 1!")
-            }
-            deprint!("\n");
-            return;
+    };
+    let (program, mut info) = match assemble_with_debug_info(&source) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{input}:{e}");
+            return ExitCode::FAILURE;
         }
-        let old_sp = self.sp;
-        loop {
-            let _val = match self.pop() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            if self.is_empty() {
-                self.sp = old_sp;
-                deprintln!("{}].", _val);
-                break;
-            }
-            deprint!("{}, ", _val);
+    };
+    if let Err(e) = fs::write(&output, program.to_bytes()) {
+        eprintln!("Couldn't write {}: {e}", output.display());
+        return ExitCode::FAILURE;
+    }
+    if debug_info {
+        info.file = Some(input.clone());
+        let path = output.with_extension("dbg");
+        if let Err(e) = fs::write(&path, info.to_string()) {
+            eprintln!("Couldn't write {}: {e}", path.display());
+            return ExitCode::FAILURE;
         }
     }
+    return ExitCode::SUCCESS;
+}
 
-    fn _eprint_upto(&mut self, i: usize) {
-        deprint!(
-            "\tStack up to {i}: SP={} LV={} LINK_PTR={} [",
-            self.sp,
-            self.lv,
-            self.data[self.lv]
-        );
-        let old_sp = self.sp;
-        let mut halt = false;
-        loop {
-            let _val = self.data[self.sp];
-            if self.sp > 0 {
-                self.sp -= 1
-            } else {
-                halt = true;
-            };
-            if self.sp < i || halt {
-                self.sp = old_sp;
-                deprintln!("{_val}({:#02x})].", _val);
-                break;
-            }
-            deprint!("{_val}({:#02x}), ", _val);
+/// `ijvrust disasm foo.ijvm [-o foo.jas]`
+fn disasm(args: &[String]) -> ExitCode {
+    let (input, output) = match args {
+        [input] => (input, None),
+        [input, flag, output] if flag == "-o" => (input, Some(output)),
+        _ => {
+            eprintln!("Usage: ijvrust disasm <input.ijvm> [-o <output.jas>]");
+            return ExitCode::from(EXIT_USAGE);
         }
-    }
+    };
 
-    fn _eprint_hex(&mut self) {
-        deprint!(
-            "\tHex stack: SP={:#02x} LV={:#02x} LINK_PTR={:#02x} [",
-            self.sp,
-            self.lv,
-            self.data[self.lv]
-        );
-        if self.sp <= self.data[self.lv] as usize + 1 {
-            deprint!("]");
-            if self.sp < self.data[self.lv] as usize + 1 {
-                deprint!("\t\tWARN: SP below LINK PTR + 1!")
-            }
-            deprint!("\n");
-            return;
+    let program = match fs::read(input)
+        .map_err(LoadError::from)
+        .and_then(|c| IjvmFile::parse(&c))
+    {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Couldn't load {input}: {e}");
+            return ExitCode::from(EXIT_USAGE);
         }
-        let old_sp = self.sp;
-        loop {
-            let _val = match self.pop() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            if self.is_empty() {
-                self.sp = old_sp;
-                deprintln!("{:#02x}].", _val);
-                break;
+    };
+    let source = disassemble(&program);
+    match output {
+        Some(output) => {
+            if let Err(e) = fs::write(output, source) {
+                eprintln!("Couldn't write {output}: {e}");
+                return ExitCode::FAILURE;
             }
-            deprint!("{:#02x}, ", _val);
         }
+        None => print!("{source}"),
     }
+    return ExitCode::SUCCESS;
 }
 
-impl Index<Word> for Stack {
-    type Output = Word;
-
-    fn index(&self, index: Word) -> &Self::Output {
-        return &self.data[index as usize];
-    }
+#[derive(Clone, Copy)]
+enum TraceFormat {
+    None,
+    Human,
+    Json,
 }
 
-impl IndexMut<Word> for Stack {
-    fn index_mut(&mut self, index: Word) -> &mut Self::Output {
-        return &mut self.data[index as usize];
-    }
+enum Input {
+    Stdin,
+    File(PathBuf),
+    Bytes(Vec<u8>),
 }
 
-pub struct Machine {
-    stack: Stack,
-    pc: i32,
-    text: Vec<Byte>,
-    text_size: Word,
-    constant_pool: Vec<Byte>,
-    halt: bool,
-    halt_msg: String,
+/// Options shared by every subcommand that runs a program.
+struct RunOptions {
+    path: String,
+    input: Input,
+    output: Option<PathBuf>,
+    max_steps: Option<u64>,
+    stack_limit: usize,
+    heap_limit: usize,
+    gc_threshold: usize,
+    main_vars: Option<usize>,
+    extended: bool,
+    network: bool,
+    input_mode: InputMode,
+    /// 0 for `--quiet`, 2 for `--verbose`.
+    verbosity: u8,
+    /// `None` unless given on the command line, so each subcommand can pick its own default.
+    trace: Option<TraceFormat>,
+    /// From `--debug-info`, or else `foo.dbg` next to `foo.ijvm` if there is one.
+    debug_info: Option<DebugInfo>,
 }
 
-const MB: usize = 262144; // number of words in a MB is 2^20 / 4
-const MAIN_LINK_PTR: Word = 257;
-const STACK_SIZE: usize = 1000 * MB;
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut path = None;
+    let mut options = RunOptions {
+        path: String::new(),
+        input: Input::Stdin,
+        output: None,
+        max_steps: None,
+        stack_limit: DEFAULT_STACK_LIMIT,
+        heap_limit: DEFAULT_HEAP_LIMIT,
+        gc_threshold: DEFAULT_GC_THRESHOLD,
+        main_vars: None,
+        extended: false,
+        network: false,
+        input_mode: InputMode::Standard,
+        verbosity: 1,
+        trace: None,
+        debug_info: None,
+    };
+    let mut debug_info_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            return args.next().ok_or_else(|| format!("{arg} needs a value"));
+        };
+        match arg.as_str() {
+            "-i" | "--input" => options.input = Input::File(PathBuf::from(value()?)),
+            "-s" | "--input-string" => options.input = Input::Bytes(value()?.clone().into_bytes()),
+            "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+            "-n" | "--max-steps" => {
+                let n = value()?;
+                options.max_steps = Some(
+                    n.parse()
+                        .map_err(|e| format!("Invalid step count {n}: {e}"))?,
+                );
+            }
+            "--stack-size" => {
+                let words = value()?;
+                options.stack_limit = words
+                    .parse()
+                    .map_err(|e| format!("Invalid stack size {words}: {e}"))?;
+                if options.stack_limit < MIN_STACK_LIMIT {
+                    return Err(format!(
+                        "Invalid stack size {words}: main's frame needs at least {MIN_STACK_LIMIT} words"
+                    ));
+                }
+            }
+            "--heap-size" => {
+                let words = value()?;
+                options.heap_limit = words
+                    .parse()
+                    .map_err(|e| format!("Invalid heap size {words}: {e}"))?;
+            }
+            "--gc-threshold" => {
+                let words = value()?;
+                options.gc_threshold = words
+                    .parse()
+                    .map_err(|e| format!("Invalid GC threshold {words}: {e}"))?;
+            }
+            "--main-vars" => {
+                let n = value()?;
+                options.main_vars = Some(
+                    n.parse()
+                        .map_err(|e| format!("Invalid number of variables {n}: {e}"))?,
+                );
+            }
+            "-t" | "--trace" => {
+                options.trace = Some(match value()?.as_str() {
+                    "none" => TraceFormat::None,
+                    "human" => TraceFormat::Human,
+                    "json" => TraceFormat::Json,
+                    other => return Err(format!("Unknown trace format {other}")),
+                })
+            }
+            "--debug-info" => debug_info_path = Some(PathBuf::from(value()?)),
+            "-x" | "--extended" => options.extended = true,
+            "--net" => options.network = true,
+            "--legacy-in" => options.input_mode = InputMode::Legacy,
+            "-v" | "--verbose" => options.verbosity = 2,
+            "-q" | "--quiet" => options.verbosity = 0,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {arg}")),
+            _ if path.is_some() => return Err(format!("Unexpected argument {arg}")),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    options.path = path.ok_or("No program given")?;
+
+    let default_path = PathBuf::from(&options.path).with_extension("dbg");
+    let debug_info_path = match debug_info_path {
+        Some(path) => Some(path),
+        None if default_path.exists() => Some(default_path),
+        None => None,
+    };
+    if let Some(path) = debug_info_path {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+        let info = DebugInfo::parse(&contents).map_err(|e| format!("{}:{e}", path.display()))?;
+        options.debug_info = Some(info);
+    }
+    return Ok(options);
+}
 
-#[derive(Debug)]
-pub enum OpError {
-    IoError(std::io::Error),
-    EmptyStackError(()),
-    GenericError(()),
+fn load(path: &str) -> Result<IjvmFile, ExitCode> {
+    return fs::read(path)
+        .map_err(LoadError::from)
+        .and_then(|c| IjvmFile::parse(&c))
+        .map_err(|e| {
+            eprintln!("Couldn't load {path}: {e}");
+            return ExitCode::from(EXIT_USAGE);
+        });
 }
 
-impl Display for OpError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            OpError::IoError(e) => write!(f, "{}", e),
-            OpError::GenericError(_) => write!(f, "OpError"),
-            OpError::EmptyStackError(_) => write!(f, "EmptyStackError"),
+/// `stdin_input` is false for the debugger, which reads its commands from stdin.
+fn build_machine(
+    program: IjvmFile,
+    options: &RunOptions,
+    stdin_input: bool,
+    default_trace: TraceFormat,
+) -> Result<Machine, ExitCode> {
+    let mut builder = Machine::builder(program)
+        .stack_limit(options.stack_limit)
+        .heap_limit(options.heap_limit)
+        .gc_threshold(options.gc_threshold)
+        .extended(options.extended)
+        .network(options.network)
+        .input_mode(options.input_mode);
+    if let Some(vars) = options.main_vars {
+        builder = builder.main_vars(vars);
+    }
+    builder = match options.trace.unwrap_or(default_trace) {
+        TraceFormat::None => builder,
+        TraceFormat::Human => {
+            let mut tracer = HumanTracer::new(io::BufWriter::new(io::stderr()));
+            if let Some(info) = &options.debug_info {
+                tracer = tracer.with_debug_info(info.clone());
+            }
+            builder.tracer(tracer)
+        }
+        TraceFormat::Json => {
+            let mut tracer = JsonTracer::new(io::BufWriter::new(io::stderr()));
+            if let Some(info) = &options.debug_info {
+                tracer = tracer.with_debug_info(info.clone());
+            }
+            builder.tracer(tracer)
         }
+    };
+    builder = match &options.input {
+        Input::Stdin if stdin_input => builder,
+        Input::Stdin => builder.input(io::empty()),
+        Input::File(path) => match fs::File::open(path) {
+            Ok(f) => builder.input(io::BufReader::new(f)),
+            Err(e) => {
+                eprintln!("Couldn't open {}: {e}", path.display());
+                return Err(ExitCode::from(EXIT_USAGE));
+            }
+        },
+        Input::Bytes(bytes) => builder.input(io::Cursor::new(bytes.clone())),
+    };
+    if let Some(path) = &options.output {
+        builder = match fs::File::create(path) {
+            Ok(f) => builder.output(f),
+            Err(e) => {
+                eprintln!("Couldn't create {}: {e}", path.display());
+                return Err(ExitCode::from(EXIT_USAGE));
+            }
+        };
     }
+    return Ok(builder.build());
 }
 
-impl From<std::io::Error> for OpError {
-    fn from(e: std::io::Error) -> Self {
-        OpError::IoError(e)
+/// Step until the machine halts or `max_steps` runs out, calling `before_step` first each time.
+fn run_until_halted(
+    machine: &mut Machine,
+    max_steps: Option<u64>,
+    mut before_step: impl FnMut(&Machine),
+) {
+    while !machine.halted() && max_steps.is_none_or(|max| machine.steps() < max) {
+        before_step(machine);
+        machine.step();
     }
 }
 
-impl From<()> for OpError {
-    fn from(_: ()) -> Self {
-        OpError::GenericError(())
+/// Report how the machine stopped and turn that into the exit status.
+fn finish(machine: &mut Machine, options: &RunOptions) -> ExitCode {
+    // The trace goes to stderr too, and should come before the report.
+    machine.flush_tracer();
+    let (code, report) = match machine.halt_reason() {
+        None => (
+            EXIT_STEP_LIMIT,
+            format!("Stopped after {} steps without halting.", machine.steps()),
+        ),
+        Some(reason) => (
+            match reason {
+                HaltReason::Halt | HaltReason::EndOfText => 0,
+                HaltReason::Err => EXIT_ERR,
+                HaltReason::Fault(_) => EXIT_FAULT,
+            },
+            format!(
+                "{reason} PC {:#06x} after {} steps.",
+                machine.pc(),
+                machine.steps()
+            ),
+        ),
+    };
+    if options.verbosity >= 1 && matches!(code, EXIT_ERR | EXIT_FAULT) {
+        let report = machine.report();
+        match &options.debug_info {
+            Some(info) => eprintln!("{}", report.debug_info(info)),
+            None => eprintln!("{report}"),
+        }
+        return ExitCode::from(code);
+    }
+    let location = options
+        .debug_info
+        .as_ref()
+        .and_then(|info| info.location(machine.op_addr()));
+    if options.verbosity >= 2 || (options.verbosity == 1 && code != 0) {
+        match location {
+            Some(location) => eprintln!("{report} Last instruction at {location}."),
+            None => eprintln!("{report}"),
+        }
     }
+    return ExitCode::from(code);
 }
 
-impl std::error::Error for OpError {}
-
-fn get_big_endian_word(buf: &Vec<Byte>, start_ptr: &mut usize) -> Word {
-    let w = ((buf[*start_ptr + 3] as Word) << 0)
-        | ((buf[*start_ptr + 2] as Word) << 8)
-        | ((buf[*start_ptr + 1] as Word) << 16)
-        | ((buf[*start_ptr + 0] as Word) << 24);
-
-    *start_ptr = *start_ptr + 4;
-    return w;
+/// `ijvrust run foo.ijvm`, or `ijvrust trace foo.ijvm` which traces in human-readable form by
+/// default.
+fn run(args: &[String], default_trace: TraceFormat) -> ExitCode {
+    let options = match parse_run_options(args) {
+        Ok(o) => o,
+        Err(e) => return usage_error(&e),
+    };
+    let mut machine =
+        match load(&options.path).and_then(|p| build_machine(p, &options, true, default_trace)) {
+            Ok(m) => m,
+            Err(code) => return code,
+        };
+    run_until_halted(&mut machine, options.max_steps, |_| ());
+    return finish(&mut machine, &options);
 }
 
-fn main() {
-    if cfg!(debug_assertions) {
-        eprintln!("Debugging enabled.\n");
-    } else {
-        eprintln!("Debugging disabled.\n");
-    }
-
-    env::set_var("RUST_BACKTRACE", "1");
-
-    deprintln!("Hello, file reading!");
-
-    let args: Vec<String> = env::args().collect();
+/// `ijvrust debug foo.ijvm`: step through a program, reading commands from stdin.
+fn debug(args: &[String]) -> ExitCode {
+    let options = match parse_run_options(args) {
+        Ok(o) => o,
+        Err(e) => return usage_error(&e),
+    };
+    let program = match load(&options.path) {
+        Ok(p) => p,
+        Err(code) => return code,
+    };
+    let machine = match build_machine(program.clone(), &options, false, TraceFormat::None) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
 
-    if args.len() != 2 {
-        eprintln!("No argument provided, exiting. Please provide an input file.");
-        return;
+    let mut debugger = Debugger::new(machine, &program).max_steps(options.max_steps);
+    if let Some(info) = &options.debug_info {
+        debugger = debugger.debug_info(info.clone());
     }
-
-    let file_path = &args[1];
-
-    deprintln!("In file {}", file_path);
-
-    let contents: Vec<Byte> = fs::read(file_path).expect("Couldn't read contents");
-
-    deprintln!(
-        "Contents are {:02x?}, length is {}",
-        contents,
-        contents.len()
-    );
-
-    const MAGIC: Word = 0x1deadfad;
-    let mut text_ptr: usize = 0;
-    let magic_num: Word = get_big_endian_word(&contents, &mut text_ptr);
-
-    deprintln!("MAGIC valid? {}.", MAGIC == magic_num);
-
-    if magic_num != MAGIC {
-        return;
+    if let Err(e) = debugger.repl(io::stdin().lock(), io::stderr()) {
+        eprintln!("Debugger I/O error: {e}");
     }
+    let mut machine = debugger.into_machine();
+    let out_of_steps = options.max_steps.is_some_and(|max| machine.steps() >= max);
+    if !machine.halted() && !out_of_steps {
+        // The user quit, which isn't something to report.
+        return ExitCode::SUCCESS;
+    }
+    return finish(&mut machine, &options);
+}
 
-    let _cp_origin: Word = get_big_endian_word(&contents, &mut text_ptr);
-    let cp_size: Word = get_big_endian_word(&contents, &mut text_ptr);
-    deprint!("cp_origin {:08x?}; cp_size {:08x?}; ", _cp_origin, cp_size);
-    let cp_data: Vec<Byte> = Vec::from(&contents[text_ptr..text_ptr + (cp_size as usize)]);
-    text_ptr += cp_size as usize;
-    deprintln!(" cp_data {:02x?}", cp_data);
-
-    let _text_origin: Word = get_big_endian_word(&contents, &mut text_ptr);
-    let text_size: Word = get_big_endian_word(&contents, &mut text_ptr);
-    let text_data: Vec<Byte> = Vec::from(&contents[text_ptr..text_ptr + (text_size as usize)]);
-    deprintln!(
-        "text_origin {:#08x?}; text_size {:#08x?}; text_data {:02x?}",
-        _text_origin,
-        text_size,
-        text_data
-    );
-
-    let mut machine = Machine {
-        text: text_data,
-        text_size: text_size,
-        pc: 0,
-        stack: Stack {
-            data: vec![0; STACK_SIZE], // TODO: keep track of which LV's have been stored?!
-            lv: 0,
-            sp: MAIN_LINK_PTR as usize + 1,
-        },
-        constant_pool: cp_data,
-        halt: false,
-        halt_msg: String::from("Generic Error."),
+/// `ijvrust stats foo.ijvm`: run a program and report what it executed.
+fn stats(args: &[String]) -> ExitCode {
+    let options = match parse_run_options(args) {
+        Ok(o) => o,
+        Err(e) => return usage_error(&e),
+    };
+    let mut machine = match load(&options.path)
+        .and_then(|p| build_machine(p, &options, true, TraceFormat::None))
+    {
+        Ok(m) => m,
+        Err(code) => return code,
     };
 
-    let lv = machine.stack.lv as i32;
-    machine.stack[lv] = MAIN_LINK_PTR;
-
-    loop {
-        if machine.halt {
-            deprintln!("Halting machine. Reason: {}", machine.halt_msg);
-            break;
+    let mut counts: HashMap<Opcode, u64> = HashMap::new();
+    let mut max_sp = machine.sp();
+    run_until_halted(&mut machine, options.max_steps, |machine| {
+        if let Some(op) = machine.next_opcode() {
+            *counts.entry(op).or_default() += 1;
         }
-
-        step(&mut machine);
+        max_sp = max_sp.max(machine.sp());
+    });
+    max_sp = max_sp.max(machine.sp());
+
+    let mut counts: Vec<(Opcode, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.byte().cmp(&b.0.byte())));
+    eprintln!("Steps:           {}", machine.steps());
+    eprintln!("Max stack depth: {max_sp} words");
+    let heap = machine.heap();
+    let gc = heap.stats();
+    if !heap.is_empty() || gc.collections > 0 {
+        eprintln!(
+            "Heap:            {} arrays, {} words",
+            heap.len(),
+            heap.words()
+        );
+        eprintln!(
+            "Collections:     {}, freeing {} arrays, {} bytes",
+            gc.collections,
+            gc.arrays_freed,
+            gc.bytes_freed()
+        );
+    }
+    for (op, count) in counts {
+        eprintln!("    {op:<14} {count}");
     }
+    return finish(&mut machine, &options);
 }
 
-fn step(machine: &mut Machine) {
-    let cur_op: Byte = machine.text[machine.pc as usize];
-    machine.pc += 1;
-    deprint!("At PC {}: {}", machine.pc - 1, match_op_code(cur_op));
-    match do_op(cur_op, machine) {
-        Ok(_) => (),
-        Err(_e) => {
-            deprint!("ERROR: {_e}");
-            machine.halt = true
-        }
+/// `ijvrust verify foo.ijvm`
+fn verify_cmd(args: &[String]) -> ExitCode {
+    let [path] = args else {
+        return usage_error("verify takes exactly one program");
     };
-
-    let _val = match machine.stack.top() {
-        Ok(val) => val,
-        Err(_) => {
-            deprint!("\ttos: Unexpected Error.");
-            machine.halt = true;
-            return;
-        }
+    let program = match load(path) {
+        Ok(p) => p,
+        Err(code) => return code,
     };
-
-    if machine.stack.is_empty() {
-        deprint!("\tstack.is_empty() true");
-    } else {
-        deprint!(
-            "\ttos: SP={}, top of stack is {_val} (hex {:#02x}).",
-            machine.stack.sp,
-            _val
-        );
+    let errors = verify(&program);
+    for e in &errors {
+        eprintln!("{path}: {e}");
     }
+    if !errors.is_empty() {
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
+}
 
-    // machine.stack._eprint();
-
-    deprintln!();
+fn usage_error(msg: &str) -> ExitCode {
+    eprintln!("{msg}\n\n{USAGE}");
+    return ExitCode::from(EXIT_USAGE);
+}
 
-    if machine.pc >= machine.text_size {
-        machine.halt = true;
-        machine.halt_msg = String::from("End of text reached.");
-    }
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let rest = args.get(2..).unwrap_or_default();
+    return match args.get(1).map(String::as_str) {
+        None | Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        Some("run") => run(rest, TraceFormat::None),
+        Some("trace") => run(rest, TraceFormat::Human),
+        Some("debug") => debug(rest),
+        Some("stats") => stats(rest),
+        Some("verify") => verify_cmd(rest),
+        Some("asm") => asm(rest),
+        Some("disasm") => disasm(rest),
+        Some(_) => run(&args[1..], TraceFormat::None),
+    };
 }
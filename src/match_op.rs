@@ -1,277 +1,275 @@
 use std::{
-    io::{stdout, Read, Write},
+    io::{ErrorKind, Read, Write},
     num::Wrapping,
 };
 
-use debug_print::{debug_eprint as deprint, debug_eprintln as deprintln};
-
-use ascii::ToAsciiChar;
-
-use crate::{Byte, Machine, OpError, Word};
-
-const BIPUSH: Byte = 0x10;
-const DUP: Byte = 0x59;
-const IADD: Byte = 0x60;
-const IAND: Byte = 0x7E;
-const IOR: Byte = 0xB0;
-const ISUB: Byte = 0x64;
-const NOP: Byte = 0x00;
-const POP: Byte = 0x57;
-const SWAP: Byte = 0x5F;
-const ERR: Byte = 0xFE;
-const HALT: Byte = 0xFF;
-const IN: Byte = 0xFC;
-const OUT: Byte = 0xFD;
-const GOTO: Byte = 0xA7;
-const IFEQ: Byte = 0x99;
-const IFLT: Byte = 0x9B;
-const IF_ICMPEQ: Byte = 0x9F;
-const LDC_W: Byte = 0x13;
-const ILOAD: Byte = 0x15;
-const ISTORE: Byte = 0x36;
-const IINC: Byte = 0x84;
-const WIDE: Byte = 0xC4;
-const INVOKEVIRTUAL: Byte = 0xB6;
-const IRETURN: Byte = 0xAC;
-
-pub fn match_op_code(op_code: Byte) -> String {
-    return match op_code {
-        0x10 => String::from("BIPUSH"),
-        0x59 => String::from("DUP"),
-        0x60 => String::from("IADD"),
-        0x7E => String::from("IAND"),
-        0xB0 => String::from("IOR"),
-        0x64 => String::from("ISUB"),
-        0x00 => String::from("NOP"),
-        0x57 => String::from("POP"),
-        0x5F => String::from("SWAP"),
-        0xFE => String::from("ERR"),
-        0xFF => String::from("HALT"),
-        0xFC => String::from("IN"),
-        0xFD => String::from("OUT"),
-        0xA7 => String::from("GOTO"),
-        0x99 => String::from("IFEQ"),
-        0x9B => String::from("IFLT"),
-        0x9F => String::from("IF_ICMPEQ"),
-        0x13 => String::from("LDC_W"),
-        0x15 => String::from("ILOAD"),
-        0x36 => String::from("ISTORE"),
-        0x84 => String::from("IINC"),
-        0xC4 => String::from("WIDE"),
-        0xB6 => String::from("INVOKEVIRTUAL"),
-        0xAC => String::from("IRETURN"),
-        _ => String::from("invalid, likely arg"),
-    };
-}
+use crate::heap::{AllocError, MAX_ARRAYS};
+use crate::io::InputMode;
+use crate::opcode::Opcode;
+use crate::stack::MAIN_LV;
+use crate::{Byte, HaltReason, Machine, OpError, Word};
 
 fn _two_operand_instruction_common(
     machine: &mut Machine,
     operation: fn(a: Word, b: Word) -> Word,
-    instruction: String,
+    op: Opcode,
 ) -> Result<(), OpError> {
-    let a = pop_safe(machine, instruction.clone())?; //as i8;
-    let b = pop_safe(machine, instruction.clone())?; //as i8;
+    let a = pop_safe(machine, op)?; //as i8;
+    let b = pop_safe(machine, op)?; //as i8;
     let res = operation(a, b);
-    machine.stack.push(res as Word);
+    push_safe(machine, res as Word)?;
     return Ok(());
 }
 
-fn two_operand_instruction_common(machine: &mut Machine, op_code: Byte) -> Result<(), OpError> {
-    let a = Wrapping(pop_safe(machine, match_op_code(op_code))?); //as i8;
-    let b = Wrapping(pop_safe(machine, match_op_code(op_code))?); //as i8;
-    machine.stack.push(match op_code {
-        IADD => (a + b).0,
-        ISUB => (b - a).0,
-        IAND => (a & b).0,
-        IOR => (a | b).0,
-        _ => return Err(OpError::GenericError(())),
-    });
+fn two_operand_instruction_common(machine: &mut Machine, op: Opcode) -> Result<(), OpError> {
+    let a = Wrapping(pop_safe(machine, op)?); //as i8;
+    let b = Wrapping(pop_safe(machine, op)?); //as i8;
+    if matches!(op, Opcode::Idiv | Opcode::Irem) && a.0 == 0 {
+        return Err(OpError::DivideByZero {
+            pc: machine.op_addr(),
+            opcode: op,
+        });
+    }
+    // Shifts use the low 5 bits of the shift count, like the JVM.
+    let shift = a.0 as u32 & 0x1F;
+    let res = match op {
+        Opcode::Iadd => (a + b).0,
+        Opcode::Isub => (b - a).0,
+        Opcode::Iand => (a & b).0,
+        Opcode::Ior => (a | b).0,
+        Opcode::Imul => (a * b).0,
+        Opcode::Idiv => b.0.wrapping_div(a.0),
+        Opcode::Irem => b.0.wrapping_rem(a.0),
+        Opcode::Shl => (b << shift as usize).0,
+        Opcode::Shr => (b >> shift as usize).0,
+        Opcode::Ushr => ((b.0 as u32) >> shift) as Word,
+        Opcode::Ixor => (a ^ b).0,
+        _ => {
+            return Err(OpError::InvalidOpcode {
+                pc: machine.op_addr(),
+                byte: op.byte(),
+            })
+        }
+    };
+    push_safe(machine, res)?;
     return Ok(());
 }
 
-pub fn do_op(op_code: Byte, machine: &mut Machine) -> Result<(), OpError> {
-    let mut ret: Result<(), OpError> = Ok(());
-    match op_code {
-        BIPUSH => {
-            machine
-                .stack
-                .push((machine.text[machine.pc as usize] as i8) as Word);
+pub fn do_op(op: Opcode, machine: &mut Machine) -> Result<(), OpError> {
+    match op {
+        Opcode::Bipush => {
+            let val = (machine.text_byte(machine.pc)? as i8) as Word;
+            push_safe(machine, val)?;
             machine.pc += 1;
         }
-        DUP => {
-            machine.stack.push(machine.stack.top()?);
+        Opcode::Dup => {
+            let val = top_safe(machine, op)?;
+            push_safe(machine, val)?;
+        }
+        Opcode::Iadd => two_operand_instruction_common(machine, op)?,
+        Opcode::Iand => two_operand_instruction_common(machine, op)?,
+        Opcode::Ior => two_operand_instruction_common(machine, op)?,
+        Opcode::Isub => two_operand_instruction_common(machine, op)?,
+        Opcode::Imul
+        | Opcode::Idiv
+        | Opcode::Irem
+        | Opcode::Shl
+        | Opcode::Shr
+        | Opcode::Ushr
+        | Opcode::Ixor => two_operand_instruction_common(machine, op)?,
+        Opcode::Newarray => {
+            let size = pop_safe(machine, op)?;
+            let Ok(len) = usize::try_from(size) else {
+                return Err(OpError::InvalidArraySize {
+                    pc: machine.op_addr(),
+                    size,
+                });
+            };
+            if machine.heap.wants_collection(len) {
+                machine.collect_garbage();
+            }
+            let reference = match machine.heap.alloc(len) {
+                Ok(reference) => reference,
+                Err(AllocError::Full) => {
+                    return Err(OpError::HeapExhausted {
+                        pc: machine.op_addr(),
+                        limit: machine.heap.limit,
+                    })
+                }
+                Err(AllocError::OutOfSlots) => {
+                    return Err(OpError::TooManyArrays {
+                        pc: machine.op_addr(),
+                        limit: MAX_ARRAYS,
+                    })
+                }
+            };
+            push_safe(machine, reference)?;
+        }
+        Opcode::Iaload => {
+            let reference = pop_safe(machine, op)?;
+            let index = pop_safe(machine, op)?;
+            let val = *array_element(machine, op, reference, index)?;
+            push_safe(machine, val)?;
+        }
+        Opcode::Iastore => {
+            let reference = pop_safe(machine, op)?;
+            let index = pop_safe(machine, op)?;
+            let val = pop_safe(machine, op)?;
+            *array_element(machine, op, reference, index)? = val;
         }
-        IADD => two_operand_instruction_common(machine, op_code)?,
-        IAND => two_operand_instruction_common(machine, op_code)?,
-        IOR => two_operand_instruction_common(machine, op_code)?,
-        ISUB => two_operand_instruction_common(machine, op_code)?,
-        NOP => (),
-        POP => {
-            pop_safe(machine, String::from("POP"))?;
+        Opcode::Gc => machine.collect_garbage(),
+        Opcode::Netbind => {
+            let port = pop_safe(machine, op)?;
+            let handle = machine.net.bind(port);
+            push_safe(machine, handle)?;
         }
-        SWAP => {
-            let a = pop_safe(machine, match_op_code(op_code))?;
-            let b = pop_safe(machine, match_op_code(op_code))?;
-            machine.stack.push(a as Word);
-            machine.stack.push(b as Word);
+        Opcode::Netconnect => {
+            let port = pop_safe(machine, op)?;
+            let host = pop_safe(machine, op)?;
+            let handle = machine.net.connect(host, port);
+            push_safe(machine, handle)?;
         }
-        ERR => {
-            machine.halt_msg = String::from("ERR reached.");
-            machine.halt = true;
+        Opcode::Netin => {
+            let handle = pop_safe(machine, op)?;
+            let byte = machine.net.read(handle);
+            push_safe(machine, byte)?;
         }
-        HALT => {
-            machine.halt_msg = String::from("HALT reached.");
-            machine.halt = true;
+        Opcode::Netout => {
+            let handle = pop_safe(machine, op)?;
+            let byte = pop_safe(machine, op)? as u8;
+            machine.net.write(handle, byte);
         }
-        IN => {
+        Opcode::Netclose => {
+            let handle = pop_safe(machine, op)?;
+            machine.net.close(handle);
+        }
+        Opcode::Ineg => {
+            let a = pop_safe(machine, op)?;
+            push_safe(machine, a.wrapping_neg())?;
+        }
+        Opcode::Nop => (),
+        Opcode::Pop => {
+            pop_safe(machine, op)?;
+        }
+        Opcode::Swap => {
+            let a = pop_safe(machine, op)?;
+            let b = pop_safe(machine, op)?;
+            push_safe(machine, a)?;
+            push_safe(machine, b)?;
+        }
+        Opcode::Err => machine.halt = Some(HaltReason::Err),
+        Opcode::Halt => machine.halt = Some(HaltReason::Halt),
+        Opcode::In => {
             let mut inb: Vec<Byte> = vec![0; 1];
-            match std::io::stdin().read_exact(&mut inb) {
-                Ok(_) => {
-                    if inb[0] as char == '\n' {
-                        deprintln!("IN: read newline (i.e. EOF), pushing 0");
-                        machine.stack.push(0);
-                    } else {
-                        machine.stack.push(inb[0] as Word)
-                    }
-                }
-                Err(e) => {
-                    machine.halt_msg = String::from(format!("IN: Error {e} when reading."));
-                    ret = Err(OpError::IoError(e));
+            match (machine.input.read_exact(&mut inb), machine.input_mode) {
+                (Ok(_), InputMode::Legacy) if inb[0] as char == '\n' => push_safe(machine, 0)?,
+                (Ok(_), _) => push_safe(machine, inb[0] as Word)?,
+                (Err(e), InputMode::Standard)
+                    if matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::WouldBlock) =>
+                {
+                    push_safe(machine, 0)?;
                 }
+                (Err(e), _) => return Err(OpError::Io(e)),
             }
-            deprintln!(
-                "\tIN: read {} with tos = {}",
-                inb[0] as Word,
-                machine.stack.top()?
-            )
         }
-        OUT => {
-            let c = pop_safe(machine, match_op_code(op_code))? as u8;
+        Opcode::Out => {
+            let c = pop_safe(machine, op)? as u8;
 
-            unsafe {
-                print!("{}", c.to_ascii_char_unchecked());
-                stdout().flush()?;
-            }
+            machine.output.write_all(&[c])?;
+            machine.output.flush()?;
         }
-        GOTO => {
-            let offset = get_short_offset(machine) as Word - 1;
-            machine.pc += offset;
+        Opcode::Goto => {
+            let offset = get_short_offset(machine)? as Word - 1;
+            machine.pc = machine.pc.wrapping_add(offset);
         } // account for step incrementing PC
-        IFEQ => {
-            if pop_safe(machine, match_op_code(op_code))? == 0 {
-                do_op(GOTO, machine)?;
+        Opcode::Ifeq => {
+            if pop_safe(machine, op)? == 0 {
+                do_op(Opcode::Goto, machine)?;
             } else {
                 machine.pc += 2;
             }
         }
-        IFLT => {
-            if (pop_safe(machine, match_op_code(op_code))?) < 0 {
-                do_op(GOTO, machine)?;
+        Opcode::Iflt => {
+            if (pop_safe(machine, op)?) < 0 {
+                do_op(Opcode::Goto, machine)?;
             } else {
                 machine.pc += 2;
             }
         }
-        IF_ICMPEQ => {
-            let a = pop_safe(machine, match_op_code(op_code))?;
-            let b = pop_safe(machine, match_op_code(op_code))?;
-            deprintln!(
-                "IF_ICMPEQ: a = {} (hex {:#02x}), b = {} (hex {:#02x})",
-                a,
-                a,
-                b,
-                b
-            );
+        Opcode::IfIcmpeq => {
+            let a = pop_safe(machine, op)?;
+            let b = pop_safe(machine, op)?;
             if a == b {
-                do_op(GOTO, machine)?;
+                do_op(Opcode::Goto, machine)?;
             } else {
                 machine.pc += 2;
             }
         }
-        LDC_W => {
-            let i = get_short_offset(machine);
+        Opcode::LdcW => {
+            let i = get_short_offset(machine)? as u16;
             let c = get_constant(machine, i)?;
-            machine.stack.push(c);
+            push_safe(machine, c)?;
             machine.pc += 2;
         }
-        ILOAD => {
-            machine.stack._eprint_upto(0);
-            let i = machine.text[machine.pc as usize] as u8;
-            load_lv(machine, i)?;
+        Opcode::Iload | Opcode::Istore | Opcode::Iinc => local_op(op, machine, false)?,
+        Opcode::Wide => {
+            let next = machine.text_byte(machine.pc)?;
             machine.pc += 1;
-            machine.stack._eprint_upto(0);
+            match Opcode::from_byte(next) {
+                Some(op) if op.can_be_wide() => local_op(op, machine, true)?,
+                _ => {
+                    return Err(OpError::InvalidWide {
+                        pc: machine.op_addr(),
+                        byte: next,
+                    })
+                }
+            }
         }
-        ISTORE => {
-            machine.stack._eprint_upto(0);
-            let i = machine.text[machine.pc as usize] as u8;
-            store_lv(machine, i)?;
-            machine.pc += 1;
-            machine.stack._eprint_upto(0);
+        Opcode::Invokevirtual => invoke(machine, op)?,
+        Opcode::Tailcall if machine.methods.len() == 1 => {
+            // Main's frame can't be replaced, so call like INVOKEVIRTUAL.
+            invoke(machine, op)?;
         }
-        IINC => {
-            // TODO: LVs are now practically reduced to i8 size, fix?!
-            let i = machine.text[machine.pc as usize] as u8;
-            machine.pc += 1;
-            let val = machine.text[machine.pc as usize] as i8;
-            machine.pc += 1;
-            deprint!(
-                "IINC: LV index {i} (= {} (hex {:#02x})) + {} (hex {:#02x})",
-                _get_lv(machine, i) as i8,
-                _get_lv(machine, i) as i8,
-                val,
-                val
-            );
-            let lv_i = calc_lv_index(machine, i);
-            machine.stack[lv_i] = machine.stack[lv_i] + val as Word;
-            deprintln!(
-                ", now {} (hex {:#02x}).",
-                _get_lv(machine, i) as i8,
-                _get_lv(machine, i) as i8
-            );
+        Opcode::Tailcall => {
+            let (method, num_args, num_lv) = callee(machine, op)?;
+            let lv = machine.stack.lv;
+            let link_ptr = machine.stack.link_ptr();
+            let ret_pc = machine.stack.data[link_ptr];
+            let ret_lv = machine.stack.data[link_ptr + 1];
+
+            // Move the arguments but OBJREF over ours, the link pointer stays at LV.
+            let args = machine.stack.sp + 1 - num_args as usize;
+            let new_link_ptr = lv + num_args as usize + num_lv as usize;
+            if !machine.stack.reserve(new_link_ptr + 1) {
+                return Err(overflow(machine));
+            }
+            let data = &mut machine.stack.data;
+            if num_args > 1 {
+                data.copy_within(args + 1..args + num_args as usize, lv + 1);
+            }
+            data[lv + num_args as usize..new_link_ptr].fill(0);
+            data[new_link_ptr] = ret_pc;
+            data[new_link_ptr + 1] = ret_lv;
+            data[lv] = new_link_ptr as Word;
+            machine.stack.sp = new_link_ptr + 1;
+
+            *machine.methods.last_mut().unwrap() = method;
+            machine.pc = method.wrapping_sub(machine.text_origin) + 4;
         }
-        WIDE => (),
-        INVOKEVIRTUAL => {
-            machine.stack._eprint_upto(255);
-
-            let old_lv = machine.stack.lv;
-            let old_pc = machine.pc + 2;
-
-            let i = get_short_offset(machine);
-            let method_pc = get_constant(machine, i)? - 1;
-            machine.pc = method_pc + 1;
-
-            // OBJREF is counted in num_args, but replaced by link pointer.
-            let num_args = get_short_offset(machine); // TODO: bug somewhere here
-            machine.pc += 2;
-
-            let num_lv = get_short_offset(machine);
-            machine.pc += 2;
-
-            machine.stack.lv = machine.stack.sp - num_args as usize + 1; // + 1;
-
-            // First make space for LVs then push old lv + pc
-            machine.stack.sp += num_lv as usize; // + 1 for objref
-
-            machine.stack.push(old_pc);
-
-            // Link Pointer points to previous PC
-            let lv = machine.stack.lv as Word;
-            machine.stack.push(old_lv as Word);
-
-            machine.stack[lv] = machine.stack.sp as Word - 1;
-
-            machine.stack._eprint_upto(255);
+        Opcode::Ireturn if machine.methods.len() == 1 => {
+            return Err(OpError::ReturnFromMain {
+                pc: machine.op_addr(),
+            });
         }
-        IRETURN => {
-            machine.stack._eprint_upto(255);
-
-            let return_value = pop_safe(machine, String::from("IRETURN"))?;
-            let lv = machine.stack.lv as Word;
-            let link_ptr = machine.stack[lv];
+        Opcode::Ireturn => {
+            let return_value = pop_safe(machine, op)?;
+            let link_ptr = machine.stack.link_ptr() as Word;
             let ret_pc = machine.stack[link_ptr];
             let ret_lv = machine.stack[link_ptr + 1] as usize;
 
             // Restore program counter.
             machine.pc = ret_pc;
+            machine.methods.pop();
 
             // Restore stack.
             machine.stack.sp = machine.stack.lv;
@@ -279,81 +277,217 @@ pub fn do_op(op_code: Byte, machine: &mut Machine) -> Result<(), OpError> {
             machine.stack.lv = ret_lv;
 
             // Link pointer of returning function needs to be popped.
-            pop_safe(machine, String::from("IRETURN"))?;
+            pop_safe(machine, op)?;
             // Return value should be placed on top of calling context's stack.
-            machine.stack.push(return_value);
+            push_safe(machine, return_value)?;
+        }
+    }
+    return Ok(());
+}
+
+/// The method an INVOKEVIRTUAL or TAILCALL calls: its address, its number of arguments
+/// including OBJREF, and its number of variables. Checks that the arguments are on the stack.
+fn callee(machine: &mut Machine, op: Opcode) -> Result<(Word, u16, u16), OpError> {
+    let i = get_short_offset(machine)? as u16;
+    // Method addresses in the constant pool include the text block's origin.
+    let method = get_constant(machine, i)?;
+    let start = method.wrapping_sub(machine.text_origin);
+
+    // OBJREF is counted in num_args, but replaced by link pointer.
+    let num_args = machine.text_u16(start)?;
+    let num_lv = machine.text_u16(start.wrapping_add(2))?;
+
+    if num_args == 0 {
+        return Err(OpError::MissingObjref {
+            pc: machine.op_addr(),
+            opcode: op,
+            method,
+        });
+    }
+    if num_args as usize > machine.stack.operands().len() {
+        return Err(underflow(machine, op));
+    }
+    return Ok((method, num_args, num_lv));
+}
+
+fn invoke(machine: &mut Machine, op: Opcode) -> Result<(), OpError> {
+    let old_lv = machine.stack.lv;
+    let old_pc = machine.pc + 2;
+
+    let (method, num_args, num_lv) = callee(machine, op)?;
+    machine.pc = method.wrapping_sub(machine.text_origin) + 4;
+    machine.methods.push(method);
+    machine.stack.lv = machine.stack.sp - num_args as usize + 1; // + 1;
+
+    // First make space for LVs then push old lv + pc
+    let locals_top = machine.stack.sp + num_lv as usize;
+    if !machine.stack.reserve(locals_top) {
+        return Err(overflow(machine));
+    }
+    machine.stack.sp = locals_top; // + 1 for objref
+
+    push_safe(machine, old_pc)?;
+
+    // Link Pointer points to previous PC
+    let lv = machine.stack.lv as Word;
+    push_safe(machine, old_lv as Word)?;
+
+    machine.stack[lv] = machine.stack.sp as Word - 1;
+    return Ok(());
+}
 
-            machine.stack._eprint_upto(255);
+/// ILOAD, ISTORE and IINC, whose local variable index is 16 bits wide after a WIDE prefix.
+fn local_op(op: Opcode, machine: &mut Machine, wide: bool) -> Result<(), OpError> {
+    let i = if wide {
+        get_short_offset(machine)? as u16
+    } else {
+        machine.text_byte(machine.pc)? as u16
+    };
+    machine.pc += if wide { 2 } else { 1 };
+    match op {
+        Opcode::Iload => {
+            load_lv(machine, i)?;
+        }
+        Opcode::Istore => {
+            store_lv(machine, i)?;
+        }
+        Opcode::Iinc => {
+            let val = machine.text_byte(machine.pc)? as i8;
+            machine.pc += 1;
+            let lv_i = writable_lv_index(machine, op, i)?;
+            machine.stack[lv_i] = machine.stack[lv_i].wrapping_add(val as Word);
         }
         _ => {
-            machine.halt_msg = String::from(format!(
-                "Error: op_code {:#02x} unknown or not implemented.",
-                op_code
-            ));
-            ret = Err(OpError::GenericError(()));
+            return Err(OpError::InvalidOpcode {
+                pc: machine.op_addr(),
+                byte: op.byte(),
+            })
         }
     }
-    return ret;
+    return Ok(());
 }
 
-fn pop_safe(machine: &mut Machine, instruction: String) -> Result<Word, OpError> {
-    match machine.stack.pop() {
-        Ok(val) => return Ok(val),
-        Err(OpError::EmptyStackError(_)) => {
-            machine.halt_msg =
-                String::from(format!("Error: Calling {instruction} on empty stack."));
-            return Err(OpError::EmptyStackError(()));
-        }
-        Err(e) => {
-            machine.halt_msg = String::from(format!(
-                "Error: Unknown error popping in {instruction} instruction."
-            ));
-            return Err(e);
-        }
+fn pop_safe(machine: &mut Machine, instruction: Opcode) -> Result<Word, OpError> {
+    return match machine.stack.pop() {
+        Some(val) => Ok(val),
+        None => Err(underflow(machine, instruction)),
+    };
+}
+
+fn top_safe(machine: &Machine, instruction: Opcode) -> Result<Word, OpError> {
+    return match machine.stack.top() {
+        Some(val) => Ok(val),
+        None => Err(underflow(machine, instruction)),
+    };
+}
+
+fn push_safe(machine: &mut Machine, val: Word) -> Result<(), OpError> {
+    if !machine.stack.push(val) {
+        return Err(overflow(machine));
     }
+    return Ok(());
+}
+
+fn overflow(machine: &Machine) -> OpError {
+    return OpError::StackOverflow {
+        pc: machine.op_addr(),
+        limit: machine.stack.limit,
+    };
+}
+
+fn underflow(machine: &Machine, instruction: Opcode) -> OpError {
+    return OpError::StackUnderflow {
+        pc: machine.op_addr(),
+        opcode: instruction,
+        method: machine.method_addr(),
+    };
+}
+
+/// Element `index` of the array `reference` refers to.
+fn array_element(
+    machine: &mut Machine,
+    opcode: Opcode,
+    reference: Word,
+    index: Word,
+) -> Result<&mut Word, OpError> {
+    let pc = machine.op_addr();
+    let Some(array) = machine.heap.get_mut(reference) else {
+        return Err(OpError::InvalidReference {
+            pc,
+            opcode,
+            value: reference,
+        });
+    };
+    let len = array.len();
+    return match usize::try_from(index).ok().and_then(|i| array.get_mut(i)) {
+        Some(element) => Ok(element),
+        None => Err(OpError::ArrayIndexOutOfBounds {
+            pc,
+            opcode,
+            index,
+            len,
+        }),
+    };
 }
 
-fn get_short_offset(machine: &Machine) -> i16 {
-    let ptr: usize = machine.pc as usize;
-    return ((machine.text[(ptr + 1) as usize] as i16) << 0) | ((machine.text[ptr] as i16) << 8);
+fn get_short_offset(machine: &Machine) -> Result<i16, OpError> {
+    return Ok(machine.text_u16(machine.pc)? as i16);
 }
 
-fn load_lv(machine: &mut Machine, index: u8) -> Result<(), OpError> {
+fn load_lv(machine: &mut Machine, index: u16) -> Result<(), OpError> {
     // TODO: make sure LV is actually stored before
-    let index = calc_lv_index(machine, index);
+    let index = calc_lv_index(machine, index)?;
     let val = machine.stack[index];
-    machine.stack.push(val);
+    push_safe(machine, val)?;
     return Ok(());
 }
 
-fn store_lv(machine: &mut Machine, index: u8) -> Result<(), OpError> {
-    // TODO: make sure there is enough LV space
-    let val = pop_safe(machine, String::from("ISTORE"))?;
-    let index = calc_lv_index(machine, index);
+fn store_lv(machine: &mut Machine, index: u16) -> Result<(), OpError> {
+    let val = pop_safe(machine, Opcode::Istore)?;
+    let index = writable_lv_index(machine, Opcode::Istore, index)?;
     machine.stack[index] = val;
     return Ok(());
 }
 
-fn calc_lv_index(machine: &mut Machine, index: u8) -> Word {
-    (machine.stack.lv + index as usize) as Word + if machine.stack.lv == 0 { 1 } else { 0 }
+/// Stack index of local `index`, which has to be in the current frame so that it can't
+/// overwrite the saved PC and LV or the operand stack.
+fn calc_lv_index(machine: &mut Machine, index: u16) -> Result<Word, OpError> {
+    let stack = &machine.stack;
+    let locals = stack.link_ptr().saturating_sub(stack.local_slot(0));
+    if index as usize >= locals {
+        return Err(OpError::LocalOutOfFrame {
+            pc: machine.op_addr(),
+            index,
+            locals,
+        });
+    }
+    return Ok(stack.local_slot(index) as Word);
 }
 
-fn _get_lv(machine: &mut Machine, index: u8) -> Word {
-    let index = calc_lv_index(machine, index);
-    return machine.stack[index];
+/// Like [`calc_lv_index`], but also keeps a method from overwriting its link pointer, which
+/// `IRETURN` and `TAILCALL` need to find the caller's frame.
+fn writable_lv_index(machine: &mut Machine, op: Opcode, index: u16) -> Result<Word, OpError> {
+    if index == 0 && machine.stack.lv != MAIN_LV {
+        return Err(OpError::LinkPointerWrite {
+            pc: machine.op_addr(),
+            opcode: op,
+        });
+    }
+    return calc_lv_index(machine, index);
 }
 
-fn get_constant(machine: &mut Machine, index: i16) -> Result<Word, OpError> {
-    let index = (index * 4) as usize;
-    let res: Result<Word, OpError> = if (index) < machine.constant_pool.len() {
-        Ok(((machine.constant_pool[index + 3] as Word) << 0)
-            | ((machine.constant_pool[index + 2] as Word) << 8)
-            | ((machine.constant_pool[index + 1] as Word) << 16)
-            | ((machine.constant_pool[index + 0] as Word) << 24))
-    } else {
-        machine.halt_msg =
-            String::from("Error: Attempting to get constant with index out of bounds.");
-        Err(OpError::GenericError(()))
-    };
-    return res;
+fn get_constant(machine: &mut Machine, index: u16) -> Result<Word, OpError> {
+    let pool_len = machine.constant_pool.len() / 4;
+    if index as usize >= pool_len {
+        return Err(OpError::ConstantOutOfBounds {
+            pc: machine.op_addr(),
+            index,
+            pool_len,
+        });
+    }
+    let index = index as usize * 4;
+    return Ok(((machine.constant_pool[index + 3] as Word) << 0)
+        | ((machine.constant_pool[index + 2] as Word) << 8)
+        | ((machine.constant_pool[index + 1] as Word) << 16)
+        | ((machine.constant_pool[index + 0] as Word) << 24));
 }
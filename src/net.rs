@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::Word;
+
+/// TCP connections opened by `NETBIND` and `NETCONNECT`, by the handle the program got for
+/// them. Like the spec says, nothing here faults: failures push 0 or are ignored.
+#[derive(Default)]
+pub struct Net {
+    pub(crate) connections: BTreeMap<Word, TcpStream>,
+    /// The last handle given out. Handles start at 1 since 0 means failure, and aren't reused.
+    pub(crate) last_handle: Word,
+}
+
+impl Net {
+    /// Listen on `port` of 127.0.0.1 and wait for a client to connect. Returns a handle for
+    /// the connection, or 0. Port 0 fails too, rather than listening on a port the program
+    /// can't learn and so waiting forever.
+    pub(crate) fn bind(&mut self, port: Word) -> Word {
+        let Ok(port @ 1..) = u16::try_from(port) else {
+            return 0;
+        };
+        let stream = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .and_then(|listener| listener.accept())
+            .map(|(stream, _)| stream);
+        return self.add(stream);
+    }
+
+    /// Connect to `port` of `host`, an IPv4 address such as 0x7F000001 for 127.0.0.1. Returns
+    /// a handle for the connection, or 0. Hosts outside of 127.0.0.0/8 always fail.
+    pub(crate) fn connect(&mut self, host: Word, port: Word) -> Word {
+        let host = Ipv4Addr::from(host as u32);
+        let Ok(port) = u16::try_from(port) else {
+            return 0;
+        };
+        if !host.is_loopback() {
+            return 0;
+        }
+        return self.add(TcpStream::connect((host, port)));
+    }
+
+    fn add(&mut self, stream: io::Result<TcpStream>) -> Word {
+        let Ok(stream) = stream else {
+            return 0;
+        };
+        // Programs send a byte at a time, which shouldn't wait for more.
+        let _ = stream.set_nodelay(true);
+        self.last_handle += 1;
+        self.connections.insert(self.last_handle, stream);
+        return self.last_handle;
+    }
+
+    /// Wait for the next byte from `handle`, or return 0 once the connection is closed, broken
+    /// or doesn't exist.
+    pub(crate) fn read(&mut self, handle: Word) -> Word {
+        let Some(stream) = self.connections.get_mut(&handle) else {
+            return 0;
+        };
+        let mut byte = [0];
+        return match stream.read(&mut byte) {
+            Ok(1) => byte[0] as Word,
+            _ => 0,
+        };
+    }
+
+    pub(crate) fn write(&mut self, handle: Word, byte: u8) {
+        if let Some(stream) = self.connections.get_mut(&handle) {
+            let _ = stream.write_all(&[byte]);
+        }
+    }
+
+    pub(crate) fn close(&mut self, handle: Word) {
+        self.connections.remove(&handle);
+    }
+
+    /// Number of open connections.
+    pub fn len(&self) -> usize {
+        return self.connections.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.connections.is_empty();
+    }
+}
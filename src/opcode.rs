@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use crate::Byte;
+
+/// The kind of an instruction operand, which determines how it is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Signed 8 bit immediate.
+    Byte,
+    /// Unsigned local variable index: 8 bits, or 16 bits after `WIDE`.
+    Var,
+    /// Signed 16 bit branch offset, relative to the opcode.
+    Offset,
+    /// Unsigned 16 bit constant pool index.
+    Constant,
+    /// Unsigned 16 bit constant pool index of a method address.
+    Method,
+}
+
+impl Operand {
+    pub fn size(self, wide: bool) -> usize {
+        return match self {
+            Operand::Byte => 1,
+            Operand::Var if !wide => 1,
+            _ => 2,
+        };
+    }
+}
+
+/// Which machines run an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionSet {
+    Standard,
+    /// Only runs on a machine built with `extended(true)`.
+    Extended,
+    /// Only runs on a machine built with `network(true)`.
+    Network,
+}
+
+/// Everything there is to know about an opcode.
+#[derive(Debug)]
+pub struct OpInfo {
+    pub mnemonic: &'static str,
+    pub byte: Byte,
+    pub operands: &'static [Operand],
+    /// Words popped off the current frame's operands, or `None` if that depends on the
+    /// method called. Like `pushes`, this is only informational: nothing checks a program's
+    /// stack depths ahead of time, since hand-written IJVM often leaves different numbers of
+    /// words on the stack on paths that meet.
+    pub pops: Option<u8>,
+    /// Words pushed onto the current frame's operands. A call's return value counts for
+    /// `INVOKEVIRTUAL` rather than `IRETURN`, which only pops it off the callee's.
+    pub pushes: u8,
+    pub set: InstructionSet,
+}
+
+macro_rules! opcodes {
+    ($($name:ident = $byte:literal, $mnemonic:literal, [$($operand:ident),*], $pops:expr, $pushes:literal, $set:ident;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u8)]
+        pub enum Opcode {
+            $($name = $byte,)*
+        }
+
+        impl Opcode {
+            pub const ALL: &'static [Opcode] = &[$(Opcode::$name,)*];
+
+            pub fn from_byte(byte: Byte) -> Option<Opcode> {
+                return match byte {
+                    $($byte => Some(Opcode::$name),)*
+                    _ => None,
+                };
+            }
+
+            pub fn info(self) -> &'static OpInfo {
+                return match self {
+                    $(Opcode::$name => &OpInfo {
+                        mnemonic: $mnemonic,
+                        byte: $byte,
+                        operands: &[$(Operand::$operand),*],
+                        pops: $pops,
+                        pushes: $pushes,
+                        set: InstructionSet::$set,
+                    },)*
+                };
+            }
+        }
+    };
+}
+
+// Adding an instruction starts here; `do_op` then needs an arm for it.
+opcodes! {
+    Nop = 0x00, "NOP", [], Some(0), 0, Standard;
+    Bipush = 0x10, "BIPUSH", [Byte], Some(0), 1, Standard;
+    LdcW = 0x13, "LDC_W", [Constant], Some(0), 1, Standard;
+    Iload = 0x15, "ILOAD", [Var], Some(0), 1, Standard;
+    Istore = 0x36, "ISTORE", [Var], Some(1), 0, Standard;
+    Pop = 0x57, "POP", [], Some(1), 0, Standard;
+    Dup = 0x59, "DUP", [], Some(1), 2, Standard;
+    Swap = 0x5F, "SWAP", [], Some(2), 2, Standard;
+    Iadd = 0x60, "IADD", [], Some(2), 1, Standard;
+    Isub = 0x64, "ISUB", [], Some(2), 1, Standard;
+    Iand = 0x7E, "IAND", [], Some(2), 1, Standard;
+    Iinc = 0x84, "IINC", [Var, Byte], Some(0), 0, Standard;
+    Ifeq = 0x99, "IFEQ", [Offset], Some(1), 0, Standard;
+    Iflt = 0x9B, "IFLT", [Offset], Some(1), 0, Standard;
+    IfIcmpeq = 0x9F, "IF_ICMPEQ", [Offset], Some(2), 0, Standard;
+    Goto = 0xA7, "GOTO", [Offset], Some(0), 0, Standard;
+    Ireturn = 0xAC, "IRETURN", [], Some(1), 0, Standard;
+    Ior = 0xB0, "IOR", [], Some(2), 1, Standard;
+    Invokevirtual = 0xB6, "INVOKEVIRTUAL", [Method], None, 1, Standard;
+    Wide = 0xC4, "WIDE", [], Some(0), 0, Standard;
+    In = 0xFC, "IN", [], Some(0), 1, Standard;
+    Out = 0xFD, "OUT", [], Some(1), 0, Standard;
+    Err = 0xFE, "ERR", [], Some(0), 0, Standard;
+    Halt = 0xFF, "HALT", [], Some(0), 0, Standard;
+    Imul = 0x68, "IMUL", [], Some(2), 1, Extended;
+    Idiv = 0x6C, "IDIV", [], Some(2), 1, Extended;
+    Irem = 0x70, "IREM", [], Some(2), 1, Extended;
+    Ineg = 0x74, "INEG", [], Some(1), 1, Extended;
+    Shl = 0x78, "SHL", [], Some(2), 1, Extended;
+    Shr = 0x7A, "SHR", [], Some(2), 1, Extended;
+    Ushr = 0x7C, "USHR", [], Some(2), 1, Extended;
+    Ixor = 0x82, "IXOR", [], Some(2), 1, Extended;
+    Newarray = 0xD1, "NEWARRAY", [], Some(1), 1, Extended;
+    Iaload = 0xD2, "IALOAD", [], Some(2), 1, Extended;
+    Iastore = 0xD3, "IASTORE", [], Some(3), 0, Extended;
+    Gc = 0xD4, "GC", [], Some(0), 0, Extended;
+    Netbind = 0xE1, "NETBIND", [], Some(1), 1, Network;
+    Netconnect = 0xE2, "NETCONNECT", [], Some(2), 1, Network;
+    Netin = 0xE3, "NETIN", [], Some(1), 1, Network;
+    Netout = 0xE4, "NETOUT", [], Some(2), 0, Network;
+    Netclose = 0xE5, "NETCLOSE", [], Some(1), 0, Network;
+    Tailcall = 0xCB, "TAILCALL", [Method], None, 1, Extended;
+}
+
+impl Opcode {
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        return Opcode::ALL
+            .iter()
+            .copied()
+            .find(|op| op.info().mnemonic.eq_ignore_ascii_case(mnemonic));
+    }
+
+    pub fn byte(self) -> Byte {
+        return self as Byte;
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        return self.info().mnemonic;
+    }
+
+    pub fn operands(self) -> &'static [Operand] {
+        return self.info().operands;
+    }
+
+    /// Whether this is one of the extensions, which aren't part of standard IJVM.
+    pub fn is_extended(self) -> bool {
+        return self.info().set != InstructionSet::Standard;
+    }
+
+    /// Whether `WIDE` may prefix this opcode.
+    pub fn can_be_wide(self) -> bool {
+        return self.operands().first() == Some(&Operand::Var);
+    }
+
+    /// Encoded length including operands, but not a `WIDE` prefix.
+    pub fn encoded_len(self, wide: bool) -> usize {
+        return 1 + self.operands().iter().map(|o| o.size(wide)).sum::<usize>();
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
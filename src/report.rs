@@ -0,0 +1,170 @@
+use std::fmt::Display;
+
+use crate::debuginfo::DebugInfo;
+use crate::disasm::{decode, Item};
+use crate::machine::Machine;
+use crate::opcode::{Opcode, Operand};
+use crate::trace::operand_name;
+use crate::Word;
+
+/// Stack words a [`Report`] shows unless told otherwise.
+pub const DEFAULT_REPORT_STACK_WORDS: usize = 8;
+
+/// Where and why a machine stopped, with the top of the stack and a backtrace, for people to
+/// read after a fault or `ERR`.
+pub struct Report<'a> {
+    machine: &'a Machine,
+    debug_info: Option<&'a DebugInfo>,
+    stack_words: usize,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(machine: &'a Machine) -> Report<'a> {
+        return Report {
+            machine,
+            debug_info: None,
+            stack_words: DEFAULT_REPORT_STACK_WORDS,
+        };
+    }
+
+    /// Name source lines, methods, labels and locals.
+    pub fn debug_info(mut self, info: &'a DebugInfo) -> Report<'a> {
+        self.debug_info = Some(info);
+        return self;
+    }
+
+    /// How many words from the top of the stack to show.
+    pub fn stack_words(mut self, words: usize) -> Report<'a> {
+        self.stack_words = words;
+        return self;
+    }
+
+    /// The instruction at `pc`, e.g. `ILOAD x` or `GOTO loop`.
+    fn instruction(&self, pc: Word) -> String {
+        let machine = self.machine;
+        let relative = pc.wrapping_sub(machine.text_origin);
+        if relative < 0 || relative >= machine.text_size {
+            return String::from("outside of the text");
+        }
+        return match decode(&machine.text, relative as usize, machine.text.len()) {
+            Item::Instruction {
+                wide, op, operands, ..
+            } => {
+                let mut line = String::from(if wide { "WIDE " } else { "" });
+                line += op.mnemonic();
+                for (kind, val) in operands {
+                    let val = match kind {
+                        Operand::Offset => machine.text_origin.wrapping_add(val),
+                        _ => val,
+                    };
+                    line += " ";
+                    line += &operand_name(self.debug_info, pc, kind, val);
+                }
+                line
+            }
+            Item::Unknown { byte, .. } => format!(".byte {byte:#04x}"),
+        };
+    }
+
+    /// E.g. `foo.jas:12`.
+    fn source_line(&self, pc: Word) -> Option<String> {
+        let info = self.debug_info?;
+        let line = info.line_at(pc)?;
+        return Some(match &info.file {
+            Some(file) => format!("{file}:{line}"),
+            None => format!("line {line}"),
+        });
+    }
+
+    /// E.g. `foo+4 (loop+2)` for an address 4 bytes into method `foo`, 2 bytes past label
+    /// `loop`, or without debug info just the method.
+    fn symbol(&self, pc: Word, method: Word, is_main: bool) -> String {
+        let known = self
+            .debug_info
+            .and_then(|info| Some((info, info.method_at(pc)?)));
+        let Some((info, m)) = known else {
+            return match is_main {
+                true => String::from("main"),
+                false => format!("the method at {method:#06x}"),
+            };
+        };
+        let mut symbol = format!("{}+{}", m.name, pc - m.start);
+        let label = info
+            .labels
+            .iter()
+            .rev()
+            .find(|(addr, _)| m.start <= *addr && *addr <= pc);
+        if let Some((addr, label)) = label {
+            symbol += &format!(" ({label}+{})", pc - addr);
+        }
+        return symbol;
+    }
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let machine = self.machine;
+        let pc = machine.op_addr();
+        match machine.halt_reason() {
+            Some(reason) => writeln!(f, "{reason}")?,
+            None => writeln!(f, "Still running.")?,
+        }
+        write!(
+            f,
+            "  at PC {pc:#06x} after {} steps: {}",
+            machine.steps(),
+            self.instruction(pc)
+        )?;
+        if let Some(location) = self.debug_info.and_then(|info| info.location(pc)) {
+            write!(f, " ({location})")?;
+        }
+
+        let stack = machine.stack();
+        let data = stack.as_slice();
+        let top = (stack.sp() + 1).min(data.len());
+        let bottom = top.saturating_sub(self.stack_words);
+        write!(
+            f,
+            "\nTop {} of {top} stack words, SP {}, LV {}:",
+            top - bottom,
+            stack.sp(),
+            stack.lv()
+        )?;
+        for i in (bottom..top).rev() {
+            write!(f, "\n  {i:>8}: {:#010x} {}", data[i], data[i])?;
+            if let Some(array) = machine.heap().get(data[i]) {
+                write!(f, " (array of {} words)", array.len())?;
+            }
+        }
+
+        write!(f, "\nBacktrace, innermost first:")?;
+        let mut shown = 0;
+        for (i, frame) in machine.frames().enumerate() {
+            // Show where each caller made its call rather than where it will continue.
+            let at = match i {
+                0 => pc,
+                _ => frame
+                    .pc
+                    .wrapping_sub(Opcode::Invokevirtual.encoded_len(false) as Word),
+            };
+            write!(
+                f,
+                "\n  #{i} {at:#06x} in {} {:?}",
+                self.symbol(at, frame.method, frame.is_main()),
+                frame.args
+            )?;
+            if let Some(line) = self.source_line(at) {
+                write!(f, " at {line}")?;
+            }
+            shown += 1;
+        }
+        if shown < machine.call_depth() {
+            write!(
+                f,
+                "\n  The other {} frames can't be found, a link pointer was overwritten.",
+                machine.call_depth() - shown
+            )?;
+        }
+        return Ok(());
+    }
+}
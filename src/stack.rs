@@ -0,0 +1,122 @@
+use std::ops::{Index, IndexMut};
+
+use crate::Word;
+
+/// Main's frame is the bottom of the stack.
+pub(crate) const MAIN_LV: usize = 0;
+
+pub struct Stack {
+    pub(crate) data: Vec<Word>,
+    pub(crate) sp: usize,
+    pub(crate) lv: usize,
+    /// Most words `data` may grow to.
+    pub(crate) limit: usize,
+}
+
+impl Stack {
+    pub fn sp(&self) -> usize {
+        return self.sp;
+    }
+
+    pub fn lv(&self) -> usize {
+        return self.lv;
+    }
+
+    /// The used part of the stack, from the bottom up to and including SP.
+    pub fn as_slice(&self) -> &[Word] {
+        return &self.data[..=self.sp];
+    }
+
+    /// The current frame's operand stack, from the bottom up to and including SP.
+    pub fn operands(&self) -> &[Word] {
+        return &self.data[(self.link_ptr() + 2).min(self.sp + 1)..=self.sp];
+    }
+
+    /// Stack index of local variable `index` in the current frame.
+    pub fn local_slot(&self, index: u16) -> usize {
+        return self.local_slot_in(self.lv, index);
+    }
+
+    /// Stack index of local variable `index` in the frame at `lv`.
+    ///
+    /// Every frame keeps its link pointer at LV, which in a method is local 0 since it
+    /// replaced the OBJREF argument. Main isn't called with OBJREF, so binaries number its
+    /// variables from 0 and they start one slot above LV. This is the one place main's frame
+    /// differs from a method's; numbering its locals like a method's would make its first
+    /// variable the link pointer, and break every existing binary.
+    pub(crate) fn local_slot_in(&self, lv: usize, index: u16) -> usize {
+        let first = if lv == MAIN_LV { 1 } else { 0 };
+        return lv + first + index as usize;
+    }
+
+    /// The current frame's local variables. In a method, local 0 is the link pointer that
+    /// replaced OBJREF.
+    pub fn locals(&self) -> &[Word] {
+        return &self.data[self.local_slot(0)..self.link_ptr()];
+    }
+
+    /// Pop the top of the current frame's operand stack, or `None` if it is empty.
+    pub(crate) fn pop(&mut self) -> Option<Word> {
+        if self.is_empty() {
+            return None;
+        }
+        let ret = self.data[self.sp];
+        self.sp -= 1;
+        return Some(ret);
+    }
+
+    /// Whether the current frame's operand stack is empty. See [`Frame`](crate::Frame) for
+    /// how a frame is laid out.
+    pub fn is_empty(&self) -> bool {
+        return self.sp <= self.link_ptr() + 1;
+    }
+
+    /// The current frame's link pointer, which points to the caller's saved PC and LV.
+    pub(crate) fn link_ptr(&self) -> usize {
+        return self.data[self.lv] as usize;
+    }
+
+    pub fn top(&self) -> Option<Word> {
+        if self.is_empty() {
+            return None;
+        }
+        return Some(self.data[self.sp]);
+    }
+
+    /// Push onto the stack, or return false if that would exceed the limit.
+    #[must_use]
+    pub(crate) fn push(&mut self, val: Word) -> bool {
+        if !self.reserve(self.sp + 1) {
+            return false;
+        }
+        self.sp += 1;
+        self.data[self.sp] = val;
+        return true;
+    }
+
+    /// Grow the stack so that `top` is a valid index, or return false if that would exceed the limit.
+    pub(crate) fn reserve(&mut self, top: usize) -> bool {
+        if top >= self.limit {
+            return false;
+        }
+        if top >= self.data.len() {
+            let len = (top + 1).max(self.data.len() * 2).min(self.limit);
+            self.data.resize(len, 0);
+        }
+        return true;
+    }
+}
+
+impl Index<Word> for Stack {
+    type Output = Word;
+
+    fn index(&self, index: Word) -> &Self::Output {
+        return &self.data[index as usize];
+    }
+}
+
+impl IndexMut<Word> for Stack {
+    fn index_mut(&mut self, index: Word) -> &mut Self::Output {
+        return &mut self.data[index as usize];
+    }
+}
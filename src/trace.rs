@@ -0,0 +1,196 @@
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crate::debuginfo::DebugInfo;
+use crate::machine::HaltReason;
+use crate::opcode::{Opcode, Operand};
+use crate::{Byte, Word};
+
+/// One executed instruction, as seen by a [`Tracer`].
+#[derive(Debug)]
+pub struct TraceEvent<'a> {
+    /// Instructions executed so far, including this one.
+    pub step: u64,
+    /// Address of the instruction, including the text block's origin.
+    pub pc: Word,
+    /// The byte at `pc`, which is the opcode or `WIDE`.
+    pub byte: Byte,
+    /// `None` if the instruction doesn't decode, so that it faulted.
+    pub op: Option<Opcode>,
+    /// Whether the instruction had a `WIDE` prefix, in which case `op` is the prefixed opcode.
+    pub wide: bool,
+    /// Operand values; branch offsets are turned into the address they jump to.
+    pub operands: &'a [Word],
+    /// The operand stack of the current frame before the instruction.
+    pub stack_before: &'a [Word],
+    /// The operand stack of the current frame after the instruction, which after
+    /// `INVOKEVIRTUAL`, `TAILCALL` and `IRETURN` is a different frame.
+    pub stack_after: &'a [Word],
+    /// Set if the instruction stopped the machine.
+    pub halt: Option<&'a HaltReason>,
+}
+
+/// Gets called by [`Machine::step`](crate::Machine::step) after each instruction.
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent<'_>);
+
+    /// Write out anything buffered, before something else reports on the same stream.
+    fn flush(&mut self) {}
+}
+
+/// Ignores everything.
+pub struct NullTracer;
+
+impl Tracer for NullTracer {
+    fn trace(&mut self, _event: &TraceEvent<'_>) {}
+}
+
+/// Writes a line per instruction, e.g. `0x0004: IADD  [19, 20] -> [39]`.
+pub struct HumanTracer<W: Write> {
+    out: W,
+    debug_info: Option<DebugInfo>,
+}
+
+impl<W: Write> HumanTracer<W> {
+    pub fn new(out: W) -> HumanTracer<W> {
+        return HumanTracer {
+            out,
+            debug_info: None,
+        };
+    }
+
+    /// Name source lines, locals and labels instead of just addresses.
+    pub fn with_debug_info(mut self, info: DebugInfo) -> HumanTracer<W> {
+        self.debug_info = Some(info);
+        return self;
+    }
+}
+
+/// An operand as the source would have it, if `info` knows its name.
+pub(crate) fn operand_name(info: Option<&DebugInfo>, pc: Word, kind: Operand, val: Word) -> String {
+    let name = match (info, kind) {
+        (Some(info), Operand::Var) => info.local_name(pc, val as u16),
+        (Some(info), Operand::Offset) => info.labels_at(val).next(),
+        _ => None,
+    };
+    return match (name, kind) {
+        (Some(name), _) => name.to_string(),
+        // Jump targets are addresses, so print them like PCs.
+        (None, Operand::Offset) => format!("{val:#06x}"),
+        (None, _) => val.to_string(),
+    };
+}
+
+impl<W: Write> Tracer for HumanTracer<W> {
+    fn trace(&mut self, e: &TraceEvent<'_>) {
+        let info = self.debug_info.as_ref();
+        let instruction = match e.op {
+            Some(op) => {
+                let mut instruction = String::from(if e.wide { "WIDE " } else { "" });
+                instruction += op.mnemonic();
+                for (kind, &val) in op.operands().iter().zip(e.operands) {
+                    write!(instruction, " {}", operand_name(info, e.pc, *kind, val)).unwrap();
+                }
+                instruction
+            }
+            None => format!(".byte {:#04x}", e.byte),
+        };
+        let location = match info.and_then(|info| info.location(e.pc)) {
+            Some(location) => format!(" {location}"),
+            None => String::new(),
+        };
+        let mut line = format!(
+            "{:#06x}{location}: {instruction:<22} {:?} -> {:?}",
+            e.pc, e.stack_before, e.stack_after
+        );
+        if let Some(reason) = e.halt {
+            write!(line, "\n{reason}").unwrap();
+        }
+        // Tracing is best effort; a closed stderr shouldn't stop the program.
+        let _ = writeln!(self.out, "{line}");
+    }
+
+    fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// Writes a JSON object per instruction, one per line.
+pub struct JsonTracer<W: Write> {
+    out: W,
+    debug_info: Option<DebugInfo>,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> JsonTracer<W> {
+        return JsonTracer {
+            out,
+            debug_info: None,
+        };
+    }
+
+    /// Add `file`, `line` and `method` to each object where they are known.
+    pub fn with_debug_info(mut self, info: DebugInfo) -> JsonTracer<W> {
+        self.debug_info = Some(info);
+        return self;
+    }
+}
+
+fn json_array(values: &[Word]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    return format!("[{}]", values.join(","));
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, e: &TraceEvent<'_>) {
+        let halt = match e.halt {
+            Some(reason) => json_string(&reason.to_string()),
+            None => String::from("null"),
+        };
+        let mut source = String::new();
+        if let Some(info) = &self.debug_info {
+            if let Some(file) = &info.file {
+                write!(source, ",\"file\":{}", json_string(file)).unwrap();
+            }
+            if let Some(line) = info.line_at(e.pc) {
+                write!(source, ",\"line\":{line}").unwrap();
+            }
+            if let Some(method) = info.method_at(e.pc) {
+                write!(source, ",\"method\":{}", json_string(&method.name)).unwrap();
+            }
+        }
+        let op = match e.op {
+            Some(op) => json_string(op.mnemonic()),
+            None => String::from("null"),
+        };
+        let _ = writeln!(
+            self.out,
+            "{{\"step\":{},\"pc\":{},\"byte\":{},\"op\":{op},\"wide\":{},\"operands\":{},\"before\":{},\"after\":{},\"halt\":{halt}{source}}}",
+            e.step,
+            e.pc,
+            e.byte,
+            e.wide,
+            json_array(e.operands),
+            json_array(e.stack_before),
+            json_array(e.stack_after),
+        );
+    }
+
+    fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+use std::fmt::Display;
+
+use crate::disasm::{decode_program, Decoded, Item};
+use crate::loader::IjvmFile;
+use crate::opcode::{Opcode, Operand};
+use crate::{Byte, Word};
+
+/// Something in a binary that would make it fault, or that the assembler never produces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Address the problem is at, including the text block's origin.
+    pub addr: Word,
+    pub msg: String,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06x}: {}", self.addr, self.msg)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+fn unknown_msg(text: &[Byte], addr: usize, byte: Byte) -> String {
+    if byte == Opcode::Wide.byte() {
+        return match text.get(addr + 1).and_then(|&b| Opcode::from_byte(b)) {
+            Some(op) if !op.can_be_wide() => format!("WIDE can't prefix {op}"),
+            Some(op) => format!("{op} is cut off by the end of its method"),
+            None => String::from("WIDE isn't followed by an instruction"),
+        };
+    }
+    return match Opcode::from_byte(byte) {
+        Some(op) => format!("{op} is cut off by the end of its method"),
+        None => format!("invalid opcode {byte:#04x}"),
+    };
+}
+
+/// Check a binary without running it: every byte of every method has to decode, branches,
+/// constants, methods and local variables have to exist, and methods mustn't write their
+/// link pointer.
+pub fn verify(program: &IjvmFile) -> Vec<VerifyError> {
+    let text = &program.text().data;
+    let origin = program.text().origin;
+    let Decoded {
+        constants,
+        method_entries,
+        regions,
+    } = decode_program(program);
+
+    let mut errors = Vec::new();
+    let mut error = |addr: usize, msg: String| {
+        errors.push(VerifyError {
+            addr: origin.wrapping_add(addr as Word),
+            msg,
+        });
+    };
+
+    for region in &regions {
+        if !region.is_main && region.end - region.start < 4 {
+            error(region.start, String::from("method header is cut off"));
+            continue;
+        }
+        if !region.is_main && region.num_args == 0 {
+            error(
+                region.start,
+                String::from("method header declares no arguments, not even OBJREF"),
+            );
+        }
+        let starts: BTreeSet<usize> = region
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Instruction { addr, .. } | Item::Unknown { addr, .. } => *addr,
+            })
+            .collect();
+        let frame_size = region.num_args as usize + region.num_vars as usize;
+
+        for item in &region.items {
+            let (addr, op, operands) = match item {
+                Item::Unknown { addr, byte } => {
+                    error(*addr, unknown_msg(text, *addr, *byte));
+                    continue;
+                }
+                Item::Instruction {
+                    addr, op, operands, ..
+                } => (*addr, *op, operands),
+            };
+            for &(kind, val) in operands {
+                match kind {
+                    Operand::Offset if !starts.contains(&(val as usize)) => error(
+                        addr,
+                        format!(
+                            "{op} jumps to {:#06x}, which isn't an instruction in this method",
+                            origin.wrapping_add(val)
+                        ),
+                    ),
+                    Operand::Constant if val as usize >= constants.len() => error(
+                        addr,
+                        format!(
+                            "{op} uses constant {val}, but the constant pool holds {}",
+                            constants.len()
+                        ),
+                    ),
+                    Operand::Method if !method_entries.contains_key(&(val as usize)) => error(
+                        addr,
+                        format!("{op} calls constant {val}, which isn't a method address"),
+                    ),
+                    Operand::Var if !region.is_main && val as usize >= frame_size => error(
+                        addr,
+                        format!("{op} uses local {val}, but the frame holds {frame_size}"),
+                    ),
+                    Operand::Var
+                        if !region.is_main
+                            && val == 0
+                            && matches!(op, Opcode::Istore | Opcode::Iinc) =>
+                    {
+                        error(
+                            addr,
+                            format!("{op} writes local 0, which holds the method's link pointer"),
+                        )
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    return errors;
+}
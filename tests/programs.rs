@@ -0,0 +1,1626 @@
+#![allow(clippy::needless_return)]
+
+use std::fs;
+use std::net::TcpListener;
+use std::thread;
+
+use ijvrust::asm::{assemble, assemble_with_debug_info};
+use ijvrust::debugger::Debugger;
+use ijvrust::disasm::disassemble;
+use ijvrust::io::{InputMode, SharedBuffer};
+use ijvrust::loader::Block;
+use ijvrust::{
+    run_with_input, verify, DebugInfo, Frame, HaltReason, Heap, HumanTracer, IjvmFile, JsonTracer,
+    LoadError, Machine, OpError, Opcode, Word,
+};
+
+/// Copies input to output until IN pushes 0.
+const ECHO: &str = "
+.main
+loop:   IN
+        DUP
+        IFEQ done
+        OUT
+        GOTO loop
+done:   HALT
+.end-main
+";
+
+fn run(path: &str) -> Machine {
+    let mut machine = Machine::from_path(path).unwrap();
+    machine.run();
+    return machine;
+}
+
+#[test]
+fn tanenbaum() {
+    let machine = run("files/Tanenbaum.ijvm");
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+}
+
+#[test]
+fn tanenbaum_output() {
+    let binary = fs::read("files/Tanenbaum.ijvm").unwrap();
+    assert_eq!(run_with_input(&binary, b"").unwrap(), b"OK");
+}
+
+/// An IJVM binary of the magic number followed by `rest`.
+fn binary(rest: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x1d, 0xea, 0xdf, 0xad];
+    bytes.extend(rest);
+    return bytes;
+}
+
+#[test]
+fn load_io_error() {
+    assert!(matches!(
+        Machine::from_path("files/missing.ijvm"),
+        Err(LoadError::Io(_))
+    ));
+}
+
+#[test]
+fn load_bad_magic() {
+    let err = IjvmFile::parse(&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap_err();
+    assert!(matches!(err, LoadError::BadMagic { found } if found as u32 == 0xcafebabe));
+}
+
+#[test]
+fn load_truncated() {
+    let truncated = |bytes: &[u8]| match IjvmFile::parse(bytes) {
+        Err(LoadError::Truncated {
+            offset,
+            expected,
+            needed,
+            available,
+        }) => (offset, expected, needed, available),
+        other => panic!("{other:?}"),
+    };
+    // The magic number itself, a block header, a block longer than the file, and bytes
+    // after the last block that don't make a header.
+    assert_eq!(truncated(&[0x1d, 0xea]), (0, "magic number".into(), 4, 2));
+    assert_eq!(
+        truncated(&binary(&[0, 0, 0, 0, 0, 0])),
+        (8, "block size".into(), 4, 2)
+    );
+    assert_eq!(
+        truncated(&binary(&[0, 0, 0, 0, 0, 0, 0, 9, 0xff])),
+        (12, "block data".into(), 9, 1)
+    );
+    assert_eq!(
+        truncated(&binary(&[0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0])),
+        (13, "block origin".into(), 4, 2)
+    );
+}
+
+#[test]
+fn load_invalid_size() {
+    let err = IjvmFile::parse(&binary(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff])).unwrap_err();
+    assert!(matches!(
+        err,
+        LoadError::InvalidSize {
+            offset: 8,
+            size: -1
+        }
+    ));
+}
+
+#[test]
+fn load_invalid_origin() {
+    let err =
+        IjvmFile::parse(&binary(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 2, 0xff, 0xff])).unwrap_err();
+    assert!(matches!(
+        err,
+        LoadError::InvalidOrigin {
+            offset: 4,
+            origin: -1,
+            size: 2
+        }
+    ));
+
+    // Ending exactly at the end of the address space is fine.
+    let file = IjvmFile::parse(&binary(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1, 0xff])).unwrap();
+    assert_eq!(file.text().origin, -1);
+}
+
+#[test]
+fn load_overlapping_blocks() {
+    let err = IjvmFile::parse(&binary(&[
+        0, 0, 0, 0, 0, 0, 0, 2, 0xff, 0xff, // text at 0
+        0, 0, 0, 1, 0, 0, 0, 1, 0xff, // a byte at 1
+    ]))
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        LoadError::OverlappingBlocks {
+            first: 0,
+            second: 1
+        }
+    ));
+}
+
+#[test]
+fn load_missing_text() {
+    let err = IjvmFile::parse(&binary(&[])).unwrap_err();
+    assert!(matches!(err, LoadError::MissingText));
+}
+
+/// Calls a method and jumps over an `ERR`, so it only prints `(` if both resolve.
+const CALL_AND_JUMP: &str = "
+.main
+        BIPUSH 0
+        BIPUSH 20
+        INVOKEVIRTUAL twice
+        OUT
+        GOTO end
+        ERR
+end:    HALT
+.end-main
+
+.method twice(x)
+        ILOAD x
+        ILOAD x
+        IADD
+        IRETURN
+.end-method
+";
+
+/// `program` with its text at `origin`, and the text block first or last. All constants must
+/// be method addresses, which include the origin.
+fn with_text_at(program: IjvmFile, origin: Word, text_first: bool) -> IjvmFile {
+    let text = Block {
+        origin,
+        data: program.text().data.clone(),
+    };
+    let mut blocks = vec![text];
+    if let Some(constant_pool) = program.constant_pool() {
+        let data = constant_pool
+            .data
+            .chunks_exact(4)
+            .map(|c| Word::from_be_bytes(c.try_into().unwrap()))
+            .flat_map(|method| method.wrapping_add(origin).to_be_bytes())
+            .collect();
+        let constant_pool = Block {
+            origin: 0x10000,
+            data,
+        };
+        match text_first {
+            true => blocks.push(constant_pool),
+            false => blocks.insert(0, constant_pool),
+        }
+    }
+    return IjvmFile::parse(&IjvmFile::new(blocks).unwrap().to_bytes()).unwrap();
+}
+
+#[test]
+fn blocks_in_any_order_and_at_any_origin() {
+    // The last text ends exactly at the end of the address space.
+    let len = assemble(CALL_AND_JUMP).unwrap().text().data.len() as Word;
+    for (origin, text_first) in [
+        (0, false),
+        (0x1000, true),
+        (0x7FFF_FFF0, false),
+        (len.wrapping_neg(), true),
+    ] {
+        let program = with_text_at(assemble(CALL_AND_JUMP).unwrap(), origin, text_first);
+        assert_eq!(program.text().origin, origin);
+        let output = SharedBuffer::new();
+        let mut machine = Machine::builder(program).output(output.clone()).build();
+        machine.run();
+        assert!(
+            matches!(machine.halt_reason(), Some(HaltReason::Halt)),
+            "{}",
+            machine.report()
+        );
+        assert_eq!(output.contents(), b"(");
+    }
+
+    // Addresses past i32::MAX wrap around, also in the report of a fault there.
+    let program = assemble(".main\nBIPUSH 0\nPOP\nPOP\n.end-main\n").unwrap();
+    let mut machine = Machine::new(with_text_at(program, 0x7FFF_FFFF, true));
+    machine.run();
+    let report = machine.report().to_string();
+    assert!(
+        report.starts_with("Error: Stack underflow at PC 0x80000002"),
+        "{report}"
+    );
+}
+
+#[test]
+fn blocks_at_other_origins_are_told_apart_by_content() {
+    let program = with_text_at(assemble(CALL_AND_JUMP).unwrap(), 0x1000, true);
+    let mut blocks = program.blocks().to_vec();
+    blocks[1].origin = 0x20000;
+    let program = IjvmFile::parse(&IjvmFile::new(blocks).unwrap().to_bytes()).unwrap();
+    assert_eq!(program.text().origin, 0x1000);
+    assert_eq!(program.constant_pool().unwrap().origin, 0x20000);
+    let output = SharedBuffer::new();
+    let mut machine = Machine::builder(program).output(output.clone()).build();
+    machine.run();
+    assert_eq!(output.contents(), b"(");
+
+    // Both blocks could be constants.
+    let blocks = vec![
+        Block {
+            origin: 0x1000,
+            data: vec![0x10, 0x01, 0x00, 0xff],
+        },
+        Block {
+            origin: 0x20000,
+            data: vec![0; 4],
+        },
+    ];
+    let err = IjvmFile::new(blocks).unwrap_err();
+    assert!(
+        matches!(&err, LoadError::AmbiguousBlocks { origins } if origins == &[0x1000, 0x20000])
+    );
+    assert_eq!(
+        err.to_string(),
+        "Can't tell the text from the constant pool among the blocks at origins 0x00001000, \
+         0x00020000, put the text at 0x00000000 or the constant pool at 0x00010000"
+    );
+}
+
+#[test]
+fn wide_locals_above_255() {
+    let machine = run("files/wide.ijvm");
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+}
+
+#[test]
+fn main_frame_holds_all_its_locals() {
+    let vars: Vec<String> = (0..300).map(|i| format!("v{i}")).collect();
+    let source = format!(
+        ".main\n.var\n{}\n.end-var\nBIPUSH 42\nISTORE v299\n{}ILOAD v299\nHALT\n.end-main\n",
+        vars.join("\n"),
+        "BIPUSH 1\n".repeat(100)
+    );
+    let program = assemble(&source).unwrap();
+    let mut machine = Machine::new(program.clone());
+    machine.run();
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+    assert_eq!(machine.stack().locals().len(), 300);
+    assert_eq!(machine.stack().operands().len(), 101);
+    assert_eq!(machine.stack().top(), Some(42));
+
+    let mut machine = Machine::builder(program).main_vars(10).build();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::LocalOutOfFrame {
+            pc: 2,
+            index: 299,
+            locals: 10
+        }))
+    ));
+
+    // Too many variables for any stack are a stack overflow, and even the smallest stack
+    // holds main's frame without them.
+    let program = assemble(".main\nHALT\n.end-main\n").unwrap();
+    let mut machine = Machine::builder(program.clone())
+        .main_vars(usize::MAX)
+        .build();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::StackOverflow { pc: 0, .. }))
+    ));
+    assert_eq!(machine.stack().sp(), 2);
+    let mut machine = Machine::builder(program).stack_limit(0).build();
+    machine.run();
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+}
+
+#[test]
+fn assembler_errors_point_at_the_source() {
+    let error = |source: &str| assemble(source).unwrap_err().to_string();
+    assert_eq!(
+        error(".main\n        BIPUSH 1\n        PUSH 2\n.end-main\n"),
+        "3:9: Unknown instruction `PUSH`."
+    );
+    assert_eq!(
+        error(".main\nloop:   GOTO  nowhere\n.end-main\n"),
+        "2:15: Unknown label `nowhere`."
+    );
+    assert_eq!(
+        error(".main\n.var\n        x\n   y\n        x\n.end-var\n.end-main\n"),
+        "5:9: Duplicate local variable `x`."
+    );
+    // Immediates are signed, only hex may give the bit pattern of a negative one.
+    assert_eq!(
+        error(
+            ".main
+        BIPUSH 200
+.end-main
+"
+        ),
+        "2:16: Immediate `200` doesn't fit in a signed byte."
+    );
+    assert_eq!(
+        error(
+            ".main
+.var
+        x
+.end-var
+        IINC x 128
+.end-main
+"
+        ),
+        "5:16: Immediate `128` doesn't fit in a signed byte."
+    );
+    let program = assemble(
+        ".main
+BIPUSH 127
+BIPUSH -128
+BIPUSH 0xC8
+.end-main
+",
+    )
+    .unwrap();
+    assert_eq!(program.text().data, [0x10, 127, 0x10, 0x80, 0x10, 0xC8]);
+}
+
+#[test]
+fn disassembly_reassembles_to_the_same_bytes() {
+    for name in ["Tanenbaum", "mandelbread", "wide"] {
+        let source = fs::read_to_string(format!("files/{name}.jas")).unwrap();
+        let program = assemble(&source).unwrap();
+        let disassembly = disassemble(&program);
+        let reassembled = assemble(&disassembly).unwrap_or_else(|e| panic!("{name}: {e}"));
+        assert_eq!(reassembled.to_bytes(), program.to_bytes(), "{name}");
+
+        // The shipped binaries came from elsewhere, but round-trip just the same.
+        let shipped = IjvmFile::parse(&fs::read(format!("files/{name}.ijvm")).unwrap()).unwrap();
+        let reassembled = assemble(&disassemble(&shipped)).unwrap();
+        assert_eq!(reassembled.to_bytes(), shipped.to_bytes(), "{name}.ijvm");
+    }
+
+    // Methods only TAILCALL reaches are found too.
+    let source = ".main\nBIPUSH 0\nTAILCALL nop\nHALT\n.end-main\n\
+                  .method nop()\nBIPUSH 0\nIRETURN\n.end-method\n";
+    let program = assemble(source).unwrap();
+    let reassembled = assemble(&disassemble(&program)).unwrap();
+    assert_eq!(reassembled.to_bytes(), program.to_bytes());
+}
+
+#[test]
+fn wide_assembles_to_shipped_binary() {
+    let source = fs::read_to_string("files/wide.jas").unwrap();
+    let binary = fs::read("files/wide.ijvm").unwrap();
+    assert_eq!(assemble(&source).unwrap().to_bytes(), binary);
+}
+
+#[test]
+fn in_keeps_newlines_and_pushes_0_at_eof() {
+    let binary = assemble(ECHO).unwrap().to_bytes();
+    let output = run_with_input(&binary, b"1 + 2\n3 * 4\n").unwrap();
+    assert_eq!(output, b"1 + 2\n3 * 4\n");
+}
+
+#[test]
+fn in_legacy_mode() {
+    let output = SharedBuffer::new();
+    let mut machine = Machine::builder(assemble(ECHO).unwrap())
+        .input(&b"ab\ncd"[..])
+        .input_mode(InputMode::Legacy)
+        .output(output.clone())
+        .build();
+    machine.run();
+    assert_eq!(output.contents(), b"ab");
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+}
+
+#[test]
+fn ldc_w_out_of_bounds_faults() {
+    let mut machine = Machine::from_bytes(&[
+        0x1d, 0xea, 0xdf, 0xad, // magic
+        0, 1, 0, 0, 0, 0, 0, 0, // empty constant pool
+        0, 0, 0, 0, 0, 0, 0, 3, // text
+        0x13, 0, 5, // LDC_W 5
+    ])
+    .unwrap();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::ConstantOutOfBounds {
+            pc: 0,
+            index: 5,
+            pool_len: 0
+        }))
+    ));
+}
+
+#[test]
+fn empty_program_ends_at_end_of_text() {
+    let mut machine = Machine::new(assemble(".main\n.end-main\n").unwrap());
+    machine.run();
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::EndOfText)));
+    assert_eq!(machine.steps(), 0);
+}
+
+#[test]
+fn fetches_outside_of_text_fault() {
+    let mut binary = assemble(".main\nGOTO end\nNOP\nend: HALT\n.end-main\n")
+        .unwrap()
+        .to_bytes();
+    // Make the GOTO's offset jump 0x100 bytes past its target.
+    let len = binary.len();
+    binary[len - 4] = 0x01;
+    let mut machine = Machine::from_bytes(&binary).unwrap();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::PcOutOfBounds {
+            from: 0,
+            to: 0x104,
+            text_size: 5
+        }))
+    ));
+
+    // BIPUSH without its operand at the end of the text.
+    let mut machine = Machine::from_bytes(&[
+        0x1d, 0xea, 0xdf, 0xad, // magic
+        0, 1, 0, 0, 0, 0, 0, 0, // empty constant pool
+        0, 0, 0, 0, 0, 0, 0, 1,    // text
+        0x10, // BIPUSH
+    ])
+    .unwrap();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::PcOutOfBounds {
+            from: 0,
+            to: 1,
+            text_size: 1
+        }))
+    ));
+}
+
+#[test]
+fn extended_arithmetic() {
+    let run_extended = |a: i8, b: i8, op: &str| {
+        let source = format!(".main\nBIPUSH {a}\nBIPUSH {b}\n{op}\nHALT\n.end-main\n");
+        let mut machine = Machine::builder(assemble(&source).unwrap())
+            .extended(true)
+            .build();
+        machine.run();
+        return (
+            machine.stack().top(),
+            machine.halt_reason().unwrap().to_string(),
+        );
+    };
+    let cases = [
+        (-6, 7, "IMUL", -42),
+        (-7, 2, "IDIV", -3),
+        (-7, 2, "IREM", -1),
+        (3, 5, "INEG", -5),
+        (3, 33, "SHL", 6),
+        (-16, 2, "SHR", -4),
+        (-1, 28, "USHR", 15),
+        (12, 10, "IXOR", 6),
+    ];
+    for (a, b, op, expected) in cases {
+        assert_eq!(run_extended(a, b, op).0, Some(expected), "{a} {b} {op}");
+    }
+    assert_eq!(
+        run_extended(1, 0, "IDIV").1,
+        "Error: Division by zero at PC 0x4: IDIV with a divisor of 0."
+    );
+
+    let mut machine =
+        Machine::new(assemble(".main\nBIPUSH 1\nBIPUSH 2\nIMUL\nHALT\n.end-main\n").unwrap());
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::InvalidOpcode {
+            pc: 4,
+            byte: 0x68
+        }))
+    ));
+}
+
+#[test]
+fn iinc_wraps_around() {
+    let source = "
+.constant
+        max 2147483647
+.end-constant
+
+.main
+.var
+        x
+.end-var
+        LDC_W max
+        ISTORE x
+        IINC x 1
+        ILOAD x
+        HALT
+.end-main
+";
+    let mut machine = Machine::new(assemble(source).unwrap());
+    machine.run();
+    assert_eq!(machine.stack().top(), Some(i32::MIN));
+}
+
+#[test]
+fn arrays_on_the_heap() {
+    // Stores i * i at index i for i below 10, then sums the array.
+    let source = "
+.main
+.var
+        a
+        i
+        sum
+.end-var
+        BIPUSH 10
+        NEWARRAY
+        ISTORE a
+fill:   ILOAD i
+        ILOAD i
+        IMUL
+        ILOAD i
+        ILOAD a
+        IASTORE
+        IINC i 1
+        ILOAD i
+        BIPUSH 10
+        IF_ICMPEQ sum
+        GOTO fill
+sum:    IINC i -1
+        ILOAD sum
+        ILOAD i
+        ILOAD a
+        IALOAD
+        IADD
+        ISTORE sum
+        ILOAD i
+        IFEQ done
+        GOTO sum
+done:   ILOAD sum
+        ILOAD i
+        ILOAD a
+        HALT
+.end-main
+";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .extended(true)
+        .build();
+    machine.run();
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+    let reference = machine.stack().top().unwrap();
+    assert!(Heap::is_ref(reference));
+    assert_eq!(
+        machine.heap().get(reference).unwrap(),
+        [0, 1, 4, 9, 16, 25, 36, 49, 64, 81]
+    );
+    assert_eq!(machine.stack().operands(), [285, 0, reference]);
+
+    let run_extended = |code: &str| {
+        let source = format!(".main\n{code}\nHALT\n.end-main\n");
+        let mut machine = Machine::builder(assemble(&source).unwrap())
+            .extended(true)
+            .heap_limit(100)
+            .build();
+        machine.run();
+        return machine.halt_reason().unwrap().to_string();
+    };
+    assert_eq!(
+        run_extended("BIPUSH 3\nBIPUSH 3\nNEWARRAY\nIALOAD"),
+        "Error: IALOAD at PC 0x5 with index 3 out of bounds for an array of 3 words."
+    );
+    assert_eq!(
+        run_extended("BIPUSH 1\nBIPUSH 0\nBIPUSH 7\nIASTORE"),
+        "Error: IASTORE at PC 0x6 on 0x7, which isn't an array reference."
+    );
+    assert_eq!(
+        run_extended("BIPUSH -1\nNEWARRAY"),
+        "Error: NEWARRAY at PC 0x2 with a negative size of -1."
+    );
+    assert_eq!(
+        run_extended("BIPUSH 101\nNEWARRAY"),
+        "Error: Out of heap at PC 0x2: the heap is limited to 100 words."
+    );
+}
+
+#[test]
+fn garbage_collection() {
+    // Creates 1000 arrays of 100 words, keeping only the last one.
+    let churn = "
+.main
+.var
+        i
+.end-var
+loop:   BIPUSH 100
+        NEWARRAY
+        POP
+        IINC i 1
+        ILOAD i
+        LDC_W n
+        IF_ICMPEQ done
+        GOTO loop
+done:   BIPUSH 1
+        NEWARRAY
+        HALT
+.end-main
+";
+    let source = format!(".constant\nn 1000\n.end-constant\n{churn}");
+    let mut machine = Machine::builder(assemble(&source).unwrap())
+        .extended(true)
+        .heap_limit(1000)
+        .gc_threshold(500)
+        .build();
+    machine.run();
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+    let stats = machine.heap().stats();
+    assert_eq!(stats.collections, 200);
+    assert_eq!(stats.arrays_freed, 1000);
+    assert_eq!(stats.bytes_freed(), 400_000);
+    assert_eq!(machine.heap().len(), 1);
+    // Collected arrays' slots are reused for 16 generations each, so the 1000 arrays took
+    // about 1000 / 16 slots.
+    let (reference, _) = machine.heap().arrays().next().unwrap();
+    assert!(reference & 0xFFFFF < 70, "{reference:#x}");
+
+    // Keeps a reference to `kept` and hides the one to `hidden` from the collector by
+    // changing its tag, so `hidden` gets collected.
+    let source = "
+.constant
+tag 0x01000000
+.end-constant
+.main
+.var
+        kept
+        hidden
+.end-var
+        BIPUSH 2
+        NEWARRAY
+        ISTORE kept
+        BIPUSH 3
+        NEWARRAY
+        LDC_W tag
+        IADD
+        ISTORE hidden
+        GC
+        BIPUSH 0
+        ILOAD kept
+        IALOAD
+        BIPUSH 0
+        ILOAD hidden
+        LDC_W tag
+        ISUB
+        IALOAD
+        HALT
+.end-main
+";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .extended(true)
+        .build();
+    machine.run();
+    assert_eq!(machine.heap().len(), 1);
+    assert_eq!(machine.heap().stats().words_freed, 3);
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::InvalidReference {
+            opcode: Opcode::Iaload,
+            value: 0x0a000001,
+            ..
+        }))
+    ));
+
+    // A collected array's slot is reused in a new generation, so the old reference to it is
+    // still invalid.
+    let source = "
+.constant
+tag 0x01000000
+.end-constant
+.main
+.var
+        stale
+        fresh
+.end-var
+        BIPUSH 1
+        NEWARRAY
+        LDC_W tag
+        IADD
+        ISTORE stale
+        GC
+        BIPUSH 1
+        NEWARRAY
+        ISTORE fresh
+        BIPUSH 0
+        ILOAD stale
+        LDC_W tag
+        ISUB
+        IALOAD
+        HALT
+.end-main
+";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .extended(true)
+        .build();
+    machine.run();
+    let references: Vec<Word> = machine.heap().arrays().map(|(r, _)| r).collect();
+    assert_eq!(references, [0x0a100000]);
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::InvalidReference {
+            value: 0x0a000000,
+            ..
+        }))
+    ));
+
+    // Reusing the slot until its generation would wrap around retires it instead, so the
+    // stale reference never refers to a live array again.
+    let source = "
+.constant
+tag 0x01000000
+.end-constant
+.main
+.var
+        stale
+        i
+.end-var
+        BIPUSH 1
+        NEWARRAY
+        LDC_W tag
+        IADD
+        ISTORE stale
+loop:   GC
+        BIPUSH 1
+        NEWARRAY
+        POP
+        IINC i 1
+        ILOAD i
+        BIPUSH 16
+        IF_ICMPEQ done
+        GOTO loop
+done:   BIPUSH 0
+        ILOAD stale
+        LDC_W tag
+        ISUB
+        IALOAD
+        HALT
+.end-main
+";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .extended(true)
+        .build();
+    machine.run();
+    let references: Vec<Word> = machine.heap().arrays().map(|(r, _)| r).collect();
+    assert_eq!(references, [0x0a000001]);
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::InvalidReference {
+            value: 0x0a000000,
+            ..
+        }))
+    ));
+
+    // Keeping them all alive runs out of references rather than heap.
+    let source = ".main\nloop: BIPUSH 0\nNEWARRAY\nGOTO loop\n.end-main\n";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .extended(true)
+        .build();
+    machine.run();
+    assert_eq!(
+        machine.halt_reason().unwrap().to_string(),
+        "Error: Out of array references at PC 0x2: at most 1048576 arrays can exist at once."
+    );
+}
+
+#[test]
+fn client_and_server_talk_over_tcp() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let constants = format!(".constant\nhost 0x7F000001\nport {port}\n.end-constant\n");
+
+    // Echoes a line back to the client, printing it too.
+    let server = "
+.main
+.var
+        net
+        c
+.end-var
+        LDC_W port
+        NETBIND
+        DUP
+        IFEQ failed
+        ISTORE net
+loop:   ILOAD net
+        NETIN
+        DUP
+        IFEQ eof
+        DUP
+        ISTORE c
+        OUT
+        ILOAD c
+        ILOAD net
+        NETOUT
+        ILOAD c
+        BIPUSH 10
+        IF_ICMPEQ close
+        GOTO loop
+eof:    POP
+close:  ILOAD net
+        NETCLOSE
+        HALT
+failed: ERR
+.end-main
+";
+    // Connects once the server is listening, sends a line and prints what comes back.
+    let send: String = "ping\n"
+        .bytes()
+        .map(|b| format!("BIPUSH {b}\nILOAD net\nNETOUT\n"))
+        .collect();
+    let client = format!(
+        "
+.main
+.var
+        net
+.end-var
+retry:  LDC_W host
+        LDC_W port
+        NETCONNECT
+        DUP
+        IFEQ failed
+        ISTORE net
+{send}
+read:   ILOAD net
+        NETIN
+        DUP
+        IFEQ eof
+        DUP
+        OUT
+        BIPUSH 10
+        IF_ICMPEQ done
+        GOTO read
+eof:    POP
+done:   ILOAD net
+        NETCLOSE
+        HALT
+failed: POP
+        GOTO retry
+.end-main
+"
+    );
+
+    let start = |source: String| {
+        let output = SharedBuffer::new();
+        let mut machine = Machine::builder(assemble(&source).unwrap())
+            .network(true)
+            .output(output.clone())
+            .build();
+        return thread::spawn(move || {
+            machine.run();
+            assert!(machine.net().is_empty());
+            return (
+                machine.halt_reason().unwrap().to_string(),
+                output.contents(),
+            );
+        });
+    };
+    let server = start(format!("{constants}{server}"));
+    let client = start(format!("{constants}{client}"));
+    for machine in [server, client] {
+        let (halt, output) = machine.join().unwrap();
+        assert_eq!(halt, "HALT reached.");
+        assert_eq!(output, b"ping\n");
+    }
+
+    // Failures push 0: reading an unknown handle, connecting to port 0 and listening on it.
+    let source = ".main\nBIPUSH 7\nNETIN\nLDC_W host\nBIPUSH 0\nNETCONNECT\n\
+                  BIPUSH 0\nNETBIND\nHALT\n.end-main\n";
+    let mut machine = Machine::builder(assemble(&format!("{constants}{source}")).unwrap())
+        .network(true)
+        .build();
+    machine.run();
+    assert_eq!(machine.stack().operands(), [0, 0, 0]);
+
+    // Only localhost is allowed, connecting elsewhere fails like any other connection.
+    let source = ".constant\nhost 0x0A000001\n.end-constant\n\
+                  .main\nLDC_W host\nBIPUSH 80\nNETCONNECT\nHALT\n.end-main\n";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .network(true)
+        .build();
+    machine.run();
+    assert!(matches!(machine.halt_reason(), Some(HaltReason::Halt)));
+    assert_eq!(machine.stack().operands(), [0]);
+
+    // The other extensions don't allow the network.
+    let source = ".main\nBIPUSH 7\nNETCLOSE\nHALT\n.end-main\n";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .extended(true)
+        .build();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::InvalidOpcode { byte: 0xE5, .. }))
+    ));
+}
+
+#[test]
+fn pop_below_frame_faults() {
+    // `drain` can't see the 7 its caller pushed before the call.
+    let source = "
+.main
+        BIPUSH 7
+        BIPUSH 0
+        INVOKEVIRTUAL drain
+        HALT
+.end-main
+
+.method drain()
+        POP
+        IRETURN
+.end-method
+";
+    let mut machine = Machine::new(assemble(source).unwrap());
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::StackUnderflow {
+            pc: 12,
+            opcode: Opcode::Pop,
+            method: 8,
+        }))
+    ));
+}
+
+#[test]
+fn methods_cant_overwrite_their_link_pointer() {
+    for instruction in ["BIPUSH -1\n        ISTORE 0", "IINC 0 -1"] {
+        let source = format!(
+            "
+.main
+        BIPUSH 0
+        INVOKEVIRTUAL clobber
+        HALT
+.end-main
+
+.method clobber()
+        {instruction}
+        BIPUSH 1
+        IRETURN
+.end-method
+"
+        );
+        let mut machine = Machine::new(assemble(&source).unwrap());
+        machine.run();
+        assert!(
+            matches!(
+                machine.halt_reason(),
+                Some(HaltReason::Fault(OpError::LinkPointerWrite { .. }))
+            ),
+            "{instruction}"
+        );
+    }
+}
+
+#[test]
+fn pop_on_empty_main_faults() {
+    let mut machine = Machine::new(assemble(".main\nPOP\n.end-main\n").unwrap());
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::StackUnderflow { pc: 0, .. }))
+    ));
+}
+
+#[test]
+fn unbounded_recursion_overflows() {
+    let source = "
+.main
+        BIPUSH 0
+        INVOKEVIRTUAL recurse
+        HALT
+.end-main
+
+.method recurse()
+        BIPUSH 0
+        INVOKEVIRTUAL recurse
+        IRETURN
+.end-method
+";
+    let mut machine = Machine::builder(assemble(source).unwrap())
+        .stack_limit(10_000)
+        .build();
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::StackOverflow {
+            limit: 10_000,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn tailcall_reuses_the_frame() {
+    // Sums 1 to n with an accumulator, calling itself last.
+    let sum_to = |n: i32, call: &str| {
+        let source = format!(
+            "
+.main
+        BIPUSH 0
+        LDC_W n
+        BIPUSH 0
+        INVOKEVIRTUAL sum
+        HALT
+.end-main
+
+.constant
+        n {n}
+.end-constant
+
+.method sum(n, acc)
+.var
+        scratch
+.end-var
+        BIPUSH 7
+        ISTORE scratch
+        ILOAD n
+        IFEQ done
+        BIPUSH 0
+        ILOAD n
+        BIPUSH 1
+        ISUB
+        ILOAD acc
+        ILOAD n
+        IADD
+        {call} sum
+        IRETURN
+done:   ILOAD acc
+        IRETURN
+.end-method
+"
+        );
+        let program = assemble(&source).unwrap();
+        assert!(verify(&program).is_empty());
+        let mut machine = Machine::builder(program).extended(true).build();
+        let (mut max_sp, mut max_depth) = (0, 0);
+        while !machine.halted() {
+            machine.step();
+            max_sp = max_sp.max(machine.sp());
+            max_depth = max_depth.max(machine.call_depth());
+        }
+        assert!(machine.halt_reason().unwrap().is_success());
+        assert_eq!(machine.stack().top(), Some(n * (n + 1) / 2));
+        return (max_sp, max_depth);
+    };
+
+    let (sp_10, depth_10) = sum_to(10, "INVOKEVIRTUAL");
+    let (sp_100, depth_100) = sum_to(100, "INVOKEVIRTUAL");
+    assert_eq!((depth_10, depth_100), (12, 102));
+    assert_eq!(sp_100 - sp_10, 90 * 6);
+
+    let tail_10 = sum_to(10, "TAILCALL");
+    let tail_100 = sum_to(100, "TAILCALL");
+    assert_eq!(tail_10, tail_100);
+    assert_eq!(tail_10.1, 2);
+    assert!(tail_10.0 < sp_10 / 5);
+}
+
+#[test]
+fn fault_report_has_backtrace() {
+    let source = "
+.main
+        BIPUSH 0
+        BIPUSH 7
+        INVOKEVIRTUAL outer
+        HALT
+.end-main
+
+.method outer(a)
+        BIPUSH 0
+        ILOAD a
+        INVOKEVIRTUAL inner
+        IRETURN
+.end-method
+
+.method inner(x)
+loop:   ILOAD x
+        IFEQ done
+        POP
+done:   POP
+        IRETURN
+.end-method
+";
+    let (program, info) = assemble_with_debug_info(source).unwrap();
+    let mut machine = Machine::new(program);
+    machine.run();
+    let report = machine
+        .report()
+        .debug_info(&info)
+        .stack_words(2)
+        .to_string();
+    let lines: Vec<&str> = report.lines().collect();
+    assert!(lines[0].starts_with("Error: Stack underflow at PC 0x1d"));
+    assert_eq!(
+        lines[1],
+        "  at PC 0x001d after 9 steps: POP (line 19 in method inner)"
+    );
+    assert_eq!(lines[2], "Top 2 of 11 stack words, SP 10, LV 7:");
+    assert_eq!(
+        &lines[5..],
+        [
+            "Backtrace, innermost first:",
+            "  #0 0x001d in inner+9 (loop+5) [7] at line 19",
+            "  #1 0x0010 in outer+8 [7] at line 12",
+            "  #2 0x0004 in main+4 [] at line 5",
+        ]
+    );
+}
+
+#[test]
+fn ireturn_from_main_faults_before_changing_the_frame() {
+    let program = assemble(".main\nBIPUSH 1\nIRETURN\n.end-main\n").unwrap();
+    let mut machine = Machine::new(program);
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::ReturnFromMain { pc: 2 }))
+    ));
+    let report = machine.report().stack_words(1).to_string();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(
+        lines[0],
+        "Error: IRETURN at PC 0x2 in main, which has no caller to return to."
+    );
+    assert_eq!(lines[2], "Top 1 of 4 stack words, SP 3, LV 0:");
+    assert_eq!(lines[3].trim(), "3: 0x00000001 1");
+    assert_eq!(
+        &lines[4..],
+        ["Backtrace, innermost first:", "  #0 0x0002 in main []"]
+    );
+}
+
+#[test]
+fn calling_a_method_without_objref_faults() {
+    // Patches twice's header to declare no arguments, which would put its link pointer over
+    // the caller's saved PC.
+    let program = assemble(CALL_AND_JUMP).unwrap();
+    let method = Word::from_be_bytes(
+        program.constant_pool().unwrap().data[..4]
+            .try_into()
+            .unwrap(),
+    );
+    let mut text = program.text().clone();
+    text.data[method as usize + 1] = 0;
+    let mut blocks = vec![text];
+    blocks.push(program.constant_pool().unwrap().clone());
+    let program = IjvmFile::new(blocks).unwrap();
+
+    let errors = verify(&program);
+    // Then x, local 1, is outside of the frame too.
+    assert_eq!(errors.len(), 3, "{errors:?}");
+    assert_eq!(
+        errors[0].to_string(),
+        format!("{method:#06x}: method header declares no arguments, not even OBJREF")
+    );
+
+    let mut machine = Machine::new(program);
+    machine.run();
+    assert!(matches!(
+        machine.halt_reason(),
+        Some(HaltReason::Fault(OpError::MissingObjref {
+            pc: 4,
+            opcode: Opcode::Invokevirtual,
+            method: m,
+        })) if *m == method
+    ));
+}
+
+#[test]
+fn verify_shipped_binaries() {
+    for path in [
+        "files/Tanenbaum.ijvm",
+        "files/mandelbread.ijvm",
+        "files/wide.ijvm",
+    ] {
+        let program = IjvmFile::parse(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(verify(&program), Vec::new(), "{path}");
+    }
+}
+
+#[test]
+fn verify_reports_bad_instructions() {
+    let program = IjvmFile::parse(&[
+        0x1d, 0xea, 0xdf, 0xad, // magic
+        0, 1, 0, 0, 0, 0, 0, 0, // empty constant pool
+        0, 0, 0, 0, 0, 0, 0, 5, // text
+        0x13, 0, 5,    // LDC_W 5
+        0xEE, // not an opcode
+        0xA7, // GOTO without an offset
+    ])
+    .unwrap();
+    let addrs: Vec<Word> = verify(&program).iter().map(|e| e.addr).collect();
+    assert_eq!(addrs, [0, 3, 4]);
+
+    // A jump past HALT and a method storing to its link pointer, with the text at 0x1000.
+    let source = "
+.main
+        BIPUSH 0
+        BIPUSH 1
+        INVOKEVIRTUAL set
+        GOTO end
+end:    HALT
+.end-main
+
+.method set(x)
+        BIPUSH 5
+        ISTORE x
+        BIPUSH 0
+        IRETURN
+.end-method
+";
+    let program = assemble(source).unwrap();
+    let mut text = program.text().clone();
+    text.data[9] += 1;
+    text.data[18] = 0;
+    let program = IjvmFile::new(vec![text, program.constant_pool().unwrap().clone()]).unwrap();
+    let errors: Vec<String> = verify(&with_text_at(program, 0x1000, true))
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            "0x1007: GOTO jumps to 0x100b, which isn't an instruction in this method",
+            "0x1011: ISTORE writes local 0, which holds the method's link pointer",
+        ]
+    );
+}
+
+#[test]
+fn json_tracer_records_each_instruction() {
+    let trace = SharedBuffer::new();
+    let program = assemble(".main\nBIPUSH 2\nBIPUSH 3\nIADD\nHALT\n.end-main\n").unwrap();
+    let mut machine = Machine::builder(program)
+        .tracer(JsonTracer::new(trace.clone()))
+        .build();
+    machine.run();
+    let trace = String::from_utf8(trace.contents()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[2],
+        r#"{"step":3,"pc":4,"byte":96,"op":"IADD","wide":false,"operands":[],"before":[2,3],"after":[5],"halt":null}"#
+    );
+    assert!(lines[3].ends_with(r#""halt":"HALT reached."}"#));
+}
+
+#[test]
+fn tracers_record_undecodable_instructions() {
+    // GOTO +3, then a byte that isn't an opcode.
+    let text = [0xa7, 0x00, 0x03, 0xee];
+    let mut rest = vec![0, 0, 0, 0, 0, 0, 0, 4];
+    rest.extend(text);
+    let program = IjvmFile::parse(&binary(&rest)).unwrap();
+
+    let trace = SharedBuffer::new();
+    let mut machine = Machine::builder(program.clone())
+        .tracer(HumanTracer::new(trace.clone()))
+        .build();
+    machine.run();
+    let trace = String::from_utf8(trace.contents()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines[0], "0x0000: GOTO 0x0003            [] -> []");
+    assert_eq!(lines[1], "0x0003: .byte 0xee             [] -> []");
+    assert_eq!(lines[2], "Error: Invalid opcode 0xee at PC 0x3.");
+
+    let trace = SharedBuffer::new();
+    let mut machine = Machine::builder(program)
+        .tracer(JsonTracer::new(trace.clone()))
+        .build();
+    machine.run();
+    let trace = String::from_utf8(trace.contents()).unwrap();
+    assert!(trace.lines().nth(1).unwrap().starts_with(
+        r#"{"step":2,"pc":3,"byte":238,"op":null,"wide":false,"operands":[],"before":[],"after":[],"#
+    ));
+}
+
+#[test]
+fn frames_show_nested_calls() {
+    let source = "
+.main
+        BIPUSH 0
+        BIPUSH 7
+        BIPUSH 8
+        INVOKEVIRTUAL outer
+        HALT
+.end-main
+
+.method outer(a, b)
+.var
+        s
+.end-var
+        ILOAD a
+        BIPUSH 0
+        ILOAD b
+        INVOKEVIRTUAL inner
+        IADD
+        IRETURN
+.end-method
+
+.method inner(x)
+        BIPUSH 3
+        ILOAD x
+        IADD
+        IRETURN
+.end-method
+";
+    let mut machine = Machine::new(assemble(source).unwrap());
+    for _ in 0..9 {
+        machine.step();
+    }
+    let frames: Vec<Frame> = machine.frames().collect();
+    assert_eq!(frames.len(), 3);
+
+    let [inner, outer, main] = frames.as_slice() else {
+        unreachable!()
+    };
+    assert_eq!((inner.method, inner.pc), (0x19, 0x1f));
+    assert_eq!(inner.return_pc, Some(0x17));
+    assert_eq!(inner.args, [8]);
+    assert_eq!(inner.locals.len(), 2);
+    assert_eq!(inner.operands, [3]);
+
+    assert_eq!((outer.method, outer.pc), (0x0a, 0x17));
+    assert_eq!(outer.return_pc, Some(0x09));
+    assert_eq!(outer.args, [7, 8]);
+    assert_eq!(&outer.locals[1..], [7, 8, 0]);
+    assert_eq!(outer.operands, [7]);
+
+    assert!(main.is_main());
+    assert_eq!((main.method, main.pc), (0, 0x09));
+    assert!(main.operands.is_empty());
+}
+
+#[test]
+fn debugger_breaks_watches_and_finishes() {
+    let source = "
+.main
+        BIPUSH 0
+        BIPUSH 5
+        INVOKEVIRTUAL count
+        OUT
+        HALT
+.end-main
+
+.method count(n)
+.var
+        i
+.end-var
+loop:   IINC i 1
+        ILOAD i
+        ILOAD n
+        IF_ICMPEQ done
+        GOTO loop
+done:   ILOAD i
+        IRETURN
+.end-method
+";
+    let program = assemble(source).unwrap();
+    let machine = Machine::builder(program.clone())
+        .output(SharedBuffer::new())
+        .build();
+    let mut debugger = Debugger::new(machine, &program);
+
+    assert_eq!(
+        debugger.execute("break method1"),
+        "Breakpoint at 0x000d (method1)"
+    );
+    assert_eq!(
+        debugger.execute("continue"),
+        "Breakpoint at 0x000d (method1)"
+    );
+    assert_eq!(debugger.machine().call_depth(), 2);
+    assert_eq!(
+        debugger.execute("watch 2"),
+        "Watching local 2 of method1, now 0"
+    );
+    assert_eq!(debugger.execute("continue"), "Local 2 changed from 0 to 1");
+    assert_eq!(
+        debugger.execute("delete method1"),
+        "Deleted breakpoint at 0x000d (method1)"
+    );
+    for i in 1..5 {
+        // An empty line repeats the last command.
+        let command = if i == 1 { "continue" } else { "" };
+        assert_eq!(
+            debugger.execute(command),
+            format!("Local 2 changed from {i} to {}", i + 1)
+        );
+    }
+    assert_eq!(
+        debugger.execute("finish"),
+        "Local 2 went out of scope, no longer watching it"
+    );
+    assert_eq!(debugger.machine().call_depth(), 1);
+    assert_eq!(debugger.execute("print stack"), "[5]");
+    assert_eq!(
+        debugger.execute("finish"),
+        "main doesn't return, try continue"
+    );
+    debugger.execute("next");
+    debugger.execute("next");
+    assert!(matches!(
+        debugger.machine().halt_reason(),
+        Some(HaltReason::Halt)
+    ));
+}
+
+#[test]
+fn debugger_shows_the_end_of_an_empty_program() {
+    let program = assemble(".main\n.end-main\n").unwrap();
+    let mut debugger = Debugger::new(Machine::new(program.clone()), &program);
+    let mut out = Vec::new();
+    debugger.repl(&b""[..], &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(": end of text\n(ijvrust) "), "{out}");
+}
+
+#[test]
+fn debug_info_maps_pcs_to_source() {
+    let source = "
+.main
+        BIPUSH 1
+        BIPUSH 2
+        INVOKEVIRTUAL add
+        HALT
+.end-main
+
+.method add(a)
+.var
+        sum
+.end-var
+        ILOAD a
+        ISTORE sum
+again:  ILOAD sum
+        IRETURN
+.end-method
+";
+    let (_, mut info) = assemble_with_debug_info(source).unwrap();
+    info.file = Some(String::from("add.jas"));
+    // The method header is at 0x8, so ILOAD a is at 0xc.
+    assert_eq!(info.location(0xc).unwrap(), "add.jas:13 in method add");
+    assert_eq!(info.location(0x8).unwrap(), "method add");
+    assert_eq!(info.local_name(0xc, 1), Some("a"));
+    assert_eq!(info.local_name(0xc, 2), Some("sum"));
+    assert_eq!(info.labels_at(0x10).collect::<Vec<_>>(), ["again"]);
+    assert_eq!(info.addr_of_line(14), Some(0xe));
+    assert_eq!(DebugInfo::parse(&info.to_string()).unwrap(), info);
+}
+
+#[test]
+fn debugger_scopes_labels_to_methods() {
+    let source = fs::read_to_string("files/mandelbread.jas").unwrap();
+    let (program, info) = assemble_with_debug_info(&source).unwrap();
+    let machine = Machine::builder(program.clone())
+        .output(SharedBuffer::new())
+        .build();
+    let mut debugger = Debugger::new(machine, &program).debug_info(info);
+
+    // Outside of div and mul, negate_a could be either's.
+    assert_eq!(
+        debugger.execute("break negate_a"),
+        "negate_a is in several methods, try one of div:negate_a at 0x010c, mul:negate_a at 0x04c1"
+    );
+    assert_eq!(
+        debugger.execute("break mul:negate_a"),
+        "Breakpoint at 0x04c1 (negate_a)"
+    );
+    assert_eq!(
+        debugger.execute("break mul:nowhere"),
+        "Method mul has no label nowhere"
+    );
+    assert_eq!(debugger.execute("break mul"), "Breakpoint at 0x0476 (mul)");
+    assert_eq!(debugger.execute("continue"), "Breakpoint at 0x0476 (mul)");
+    // Inside mul, its own labels win over mandelbrot's.
+    assert_eq!(
+        debugger.execute("break for_loop"),
+        "Breakpoint at 0x049b (for_loop)"
+    );
+    assert_eq!(
+        debugger.execute("continue"),
+        "Breakpoint at 0x049b (for_loop)"
+    );
+}
+
+#[test]
+fn debugger_steps_over_tailcall_from_main() {
+    let source = "
+.main
+        BIPUSH 0
+        BIPUSH 3
+        TAILCALL down
+        HALT
+.end-main
+
+.method down(n)
+        ILOAD n
+        IFEQ done
+        BIPUSH 0
+        ILOAD n
+        BIPUSH 1
+        ISUB
+        TAILCALL down
+done:   BIPUSH 7
+        IRETURN
+.end-method
+";
+    let program = assemble(source).unwrap();
+    let machine = Machine::builder(program.clone()).extended(true).build();
+    let mut debugger = Debugger::new(machine, &program);
+    debugger.execute("step 2");
+    // From main, TAILCALL pushes a frame like INVOKEVIRTUAL, so next runs the whole call.
+    debugger.execute("next");
+    assert_eq!(debugger.machine().call_depth(), 1);
+    assert_eq!(debugger.machine().stack().operands(), [7]);
+    assert_eq!(debugger.machine().steps(), 3 + 3 * 7 + 4);
+}
+
+#[test]
+fn debugger_finds_labels_that_share_an_address() {
+    let source = "
+.main
+first:
+second: BIPUSH 0
+        BIPUSH 3
+        INVOKEVIRTUAL count
+        HALT
+.end-main
+
+.method count(n)
+top:    ILOAD n
+        IRETURN
+.end-method
+";
+    let (program, info) = assemble_with_debug_info(source).unwrap();
+    assert_eq!(info.labels_at(0).collect::<Vec<_>>(), ["first", "second"]);
+    assert_eq!(DebugInfo::parse(&info.to_string()).unwrap(), info);
+    let mut debugger = Debugger::new(Machine::new(program.clone()), &program).debug_info(info);
+
+    // A method's name and the labels on its first instruction all name it.
+    assert_eq!(
+        debugger.execute("break first"),
+        "Breakpoint at 0x0000 (main, first, second)"
+    );
+    assert_eq!(
+        debugger.execute("delete second"),
+        "Deleted breakpoint at 0x0000 (main, first, second)"
+    );
+    assert_eq!(
+        debugger.execute("break top"),
+        "Breakpoint at 0x000c (count, top)"
+    );
+    assert_eq!(
+        debugger.execute("continue"),
+        "Breakpoint at 0x000c (count, top)"
+    );
+    assert!(debugger
+        .execute("print frame")
+        .starts_with("count at depth 2"));
+}
+
+#[test]
+fn debugger_steps_into_methods_past_i32_max() {
+    // twice starts just below i32::MAX, and its first instruction just above.
+    let program = with_text_at(assemble(CALL_AND_JUMP).unwrap(), 0x7FFF_FFF0, false);
+    let machine = Machine::builder(program.clone())
+        .output(SharedBuffer::new())
+        .build();
+    let mut debugger = Debugger::new(machine, &program);
+    debugger.execute("step 3");
+    assert_eq!(debugger.machine().call_depth(), 2);
+    let frame = debugger.execute("print frame");
+    assert!(
+        frame.starts_with("method1 at depth 2, started at 0x7ffffffd"),
+        "{frame}"
+    );
+    assert_eq!(debugger.execute("finish"), "");
+    assert_eq!(debugger.machine().stack().operands(), [40]);
+}